@@ -5,7 +5,7 @@
 use convert_case::{Case, Casing};
 use proc_macro2::{Ident, Span, TokenStream};
 use quote::quote;
-use serde_json::{Map, Value};
+use serde_json::{json, Map, Value};
 use std::{
     env,
     fs::{self, File},
@@ -13,7 +13,48 @@ use std::{
     path::PathBuf,
 };
 
-fn gen_primary(properties_obj: &Map<String, Value>) -> TokenStream {
+// Fields that identify an instance in the state table, but aren't in the
+// server's model. Added to the generated types, and cleared before upload.
+static LOCAL_FIELDS: &[(&str, &str, &str)] = &[
+    // Name of the jack's input device
+    ("PeripheralAudioPort", "port", "string"),
+    // From EDID
+    ("Display", "manufacturer", "string"),
+    ("Display", "product_code", "integer"),
+    ("Display", "serial_number", "integer"),
+    // Performance is reported once per boot
+    ("BootPerformance", "boot_id", "string"),
+    // Hash of the ICCID
+    ("PeripheralSIMCard", "sim_id", "string"),
+];
+
+// Types that can have multiple instances, but have no primary key in the
+// schema, or one that doesn't identify an instance. These fields aren't
+// required, so are `Option`s, and may be `LOCAL_FIELDS`.
+static EXTRA_PRIMARY_KEYS: &[(&str, &str)] = &[
+    // PCI slot or USB port, which is stable unlike a MAC address
+    ("NetworkCard", "bus_info"),
+    // Each jack of a sound card is a separate input device
+    ("PeripheralAudioPort", "port"),
+    // A different monitor on the same port is a different display
+    ("Display", "manufacturer"),
+    ("Display", "product_code"),
+    ("Display", "serial_number"),
+    ("BootPerformance", "boot_id"),
+    // A different SIM in the same modem is a different card
+    ("PeripheralSIMCard", "sim_id"),
+];
+
+// Fields that can differ between reads of the same instance, so aren't
+// diffed
+static UNSTABLE_FIELDS: &[(&str, &str)] = &[
+    // Without realtime timestamps from systemd, based on `btime` from
+    // `/proc/stat`, which jitters
+    ("BootPerformance", "last_boot_time"),
+    ("BootPerformance", "time"),
+];
+
+fn gen_primary(properties_obj: &Map<String, Value>, extra_primary_keys: &[&str]) -> TokenStream {
     let mut primary_keys: Vec<_> = properties_obj
         .iter()
         .filter_map(|(k, v)| {
@@ -31,15 +72,26 @@ fn gen_primary(properties_obj: &Map<String, Value>) -> TokenStream {
         .collect();
     primary_keys.sort();
 
+    let extra_primary_keys = extra_primary_keys
+        .iter()
+        .map(|k| Ident::new(k, Span::call_site()));
+
     quote! {
-        vec![#(x.#primary_keys.to_string()),*]
+        vec![
+            #(x.#primary_keys.to_string(),)*
+            #(x.#extra_primary_keys.as_ref().map(ToString::to_string).unwrap_or_default()),*
+        ]
     }
 }
 
-fn gen_diff(properties_obj: &Map<String, Value>, required: &[&str]) -> TokenStream {
+fn gen_diff(
+    properties_obj: &Map<String, Value>,
+    required: &[&str],
+    unstable: &[&str],
+) -> TokenStream {
     let mut props = Vec::new();
     for (k, _) in properties_obj.iter() {
-        if k == "state" || k == "timestamp" {
+        if k == "state" || k == "timestamp" || unstable.contains(&k.as_str()) {
             continue;
         }
 
@@ -99,11 +151,47 @@ fn gen_clear_options(properties_obj: &Map<String, Value>, required: &[&str]) ->
     }
 }
 
+fn gen_clear_local(type_: &str) -> TokenStream {
+    let props = LOCAL_FIELDS
+        .iter()
+        .filter(|(i, _, _)| *i == type_)
+        .map(|(_, k, _)| Ident::new(k, Span::call_site()));
+
+    quote! {
+        {
+            #(inner.#props = None;)*
+        }
+    }
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=DataUploadRequestModel.json");
 
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
     let json_str = fs::read_to_string("DataUploadRequestModel.json").unwrap();
-    let root: Value = serde_json::from_str(&json_str).unwrap();
+    let mut root: Value = serde_json::from_str(&json_str).unwrap();
+    for (type_, k, json_type) in LOCAL_FIELDS {
+        root.pointer_mut(&format!("/definitions/{}/properties", type_))
+            .unwrap()
+            .as_object_mut()
+            .unwrap()
+            .insert(
+                k.to_string(),
+                json!({ "description": "Not uploaded", "type": json_type }),
+            );
+    }
+
+    // Generate types from the model with local fields
+    let model_path = out_dir.join("DataUploadRequestModel.json");
+    fs::write(&model_path, root.to_string()).unwrap();
+    let types = schemafy_lib::Generator::builder()
+        .with_input_file(&model_path)
+        .build()
+        .generate();
+    let mut file = File::create(out_dir.join("event_types.rs")).unwrap();
+    writeln!(file, "{}", types).unwrap();
+
     let mut names = Vec::new();
     let mut variants = Vec::new();
     let mut structs = Vec::new();
@@ -112,6 +200,7 @@ fn main() {
     let mut primaries = Vec::new();
     let mut diffs = Vec::new();
     let mut clear_options = Vec::new();
+    let mut clear_locals = Vec::new();
     for (k, v) in root
         .pointer("/definitions/AnyTelemetryEvent/properties")
         .unwrap()
@@ -133,7 +222,13 @@ fn main() {
             .unwrap();
         let properties_obj = properties.as_object().unwrap();
 
-        let required = root
+        let extra_primary_keys: Vec<&str> = EXTRA_PRIMARY_KEYS
+            .iter()
+            .filter(|(i, _)| *i == type_)
+            .map(|(_, k)| *k)
+            .collect();
+
+        let mut required = root
             .pointer(&format!("/definitions/{}/required", type_))
             .map_or_else(Vec::new, |x| {
                 x.as_array()
@@ -142,6 +237,8 @@ fn main() {
                     .map(|x| x.as_str().unwrap())
                     .collect()
             });
+        // Keep primary keys in `updated` and `removed` events
+        required.extend(&extra_primary_keys);
 
         if let Some(ref_) = properties.pointer("/state/$ref") {
             assert_eq!(ref_, "#/definitions/State");
@@ -152,9 +249,16 @@ fn main() {
             mut_states.push(quote! { None });
         };
 
-        primaries.push(gen_primary(properties_obj));
-        diffs.push(gen_diff(properties_obj, &required));
+        let unstable: Vec<&str> = UNSTABLE_FIELDS
+            .iter()
+            .filter(|(i, _)| *i == type_)
+            .map(|(_, k)| *k)
+            .collect();
+
+        primaries.push(gen_primary(properties_obj, &extra_primary_keys));
+        diffs.push(gen_diff(properties_obj, &required, &unstable));
         clear_options.push(gen_clear_options(properties_obj, &required));
+        clear_locals.push(gen_clear_local(type_));
     }
 
     let tokens = quote! {
@@ -240,12 +344,17 @@ fn main() {
                     #(TelemetryEvent::#variants(inner) => #clear_options),*
                 }
             }
+
+            // Removes fields that aren't in the server's model
+            #[allow(unused_variables)]
+            fn clear_local(&mut self) {
+                match self {
+                    #(TelemetryEvent::#variants(inner) => #clear_locals),*
+                }
+            }
         }
     };
 
-    let out_dir = env::var_os("OUT_DIR").unwrap();
-    let mut path = PathBuf::from(out_dir);
-    path.push("event_enum.rs");
-    let mut file = File::create(path).unwrap();
+    let mut file = File::create(out_dir.join("event_enum.rs")).unwrap();
     writeln!(file, "{}", tokens).unwrap();
 }
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use once_cell::sync::Lazy;
-use std::fs;
+use std::{collections::HashMap, fs};
 
 const DEFAULT_ENDPOINT_URL: &str = "https://api.data.hpdevone.com";
 const CONF_PATH: &str = "/etc/hp-vendor.conf";
@@ -14,6 +14,66 @@ pub struct HpVendorConf {
     endpoint_url: Option<String>,
     #[serde(default)]
     pub allow_unsupported_hardware: bool,
+    /// Sensor source by zone, overriding the profile for the board
+    #[serde(default)]
+    pub sensors: HashMap<String, String>,
+    /// Overrides the sampling config from the server
+    #[serde(default)]
+    pub sampling: SamplingConf,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+/// Caps on data kept while it can't be summarized or uploaded. Past a cap,
+/// the oldest or least important data is dropped, and counted.
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Events in the upload queue
+    pub queued_events: usize,
+    /// Thermal and fan samples not yet summarized, of each
+    pub samples: usize,
+    /// Writes buffered by the daemon while the database is failing
+    pub pending: usize,
+    /// Devices the daemon tracks for `on_change` events
+    pub udev_devices: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            queued_events: 10_000,
+            // Two weeks at the default interval
+            samples: 20_160,
+            pending: 10_000,
+            udev_devices: 1_000,
+        }
+    }
+}
+
+/// Intervals between thermal samples, in seconds. Unset fields use the
+/// defaults, or disable faster sampling.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SamplingConf {
+    pub interval: Option<u64>,
+    /// Used instead while on battery
+    pub battery_interval: Option<u64>,
+    /// Used instead while a zone is at or above `stress_temp`
+    pub stress_interval: Option<u64>,
+    /// Degrees Celsius
+    pub stress_temp: Option<i64>,
+}
+
+impl SamplingConf {
+    /// Fields of `self`, or of `other` where unset
+    pub fn or(&self, other: &Self) -> Self {
+        Self {
+            interval: self.interval.or(other.interval),
+            battery_interval: self.battery_interval.or(other.battery_interval),
+            stress_interval: self.stress_interval.or(other.stress_interval),
+            stress_temp: self.stress_temp.or(other.stress_temp),
+        }
+    }
 }
 
 impl HpVendorConf {
@@ -2,243 +2,1104 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-// XXX memory usage? Is there any danger remove events won't occur, and memory will grow?
-
 use mio::{unix::SourceFd, Token};
 use nix::{
+    errno::Errno,
     sys::{
         signal::{self, SigSet},
-        signalfd::SignalFd,
-        time::TimeSpec,
+        signalfd::{SfdFlags, SignalFd},
+        time::{TimeSpec, TimeValLike},
         timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags},
     },
     unistd,
 };
 use std::{
-    collections::HashMap,
-    fs::OpenOptions,
-    io::{ErrorKind, Seek, SeekFrom},
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Seek, SeekFrom},
+    mem,
     os::unix::{fs::OpenOptionsExt, io::AsRawFd},
-    str,
-    time::Duration,
+    path::{Path, PathBuf},
+    process, str,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 
+use super::daily;
 use crate::{
     config::SamplingFrequency,
     db::{self, DB},
-    event, util,
+    event::{self, TelemetryEvent, TelemetryEventType},
+    frequency::Frequencies,
+    trigger::Trigger,
+    util, UdevDescs,
 };
 
 const TOKEN_SIGNAL: Token = Token(0);
 const TOKEN_UDEV: Token = Token(1);
 const TOKEN_KMSG: Token = Token(2);
 const TOKEN_TIMER: Token = Token(3);
+const TOKEN_DAILY: Token = Token(4);
+const TOKEN_SLEEP: Token = Token(5);
+const TOKEN_COLLECT_DONE: Token = Token(6);
+const TOKEN_SIM: Token = Token(7);
+const TOKEN_STARTUP: Token = Token(8);
+// Followed by a token for each audio jack
+const TOKEN_JACK: Token = Token(9);
 
-// https://www.kernel.org/doc/Documentation/ABI/testing/dev-kmsg
-fn parse_kmsg(buf: &[u8]) -> Option<()> {
-    let record = str::from_utf8(buf).ok()?;
-    let mut lines = record.lines();
+pub const LOCK: &str = "/var/hp-vendor/daemon.lock";
+
+// Backoff between attempts to write to the database
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(5 * 60);
+
+// A D-Bus monitor that exits sooner than this after starting isn't restarted,
+// since it would likely exit again
+const MONITOR_MIN_UPTIME: Duration = Duration::from_secs(60);
 
-    let (_props, message) = lines.next()?.split_once(';')?;
+// Delay before running daily collection again if it failed
+const DAILY_RETRY: Duration = Duration::from_secs(60 * 60);
 
-    let mut subsystem = None;
-    let mut device = None;
-    for i in lines {
-        if let Some(value) = i.strip_prefix(" SUBSYSTEM=") {
-            subsystem = Some(value);
-        } else if let Some(value) = i.strip_prefix(" DEVICE=") {
-            device = Some(value);
+// Errors that stop the daemon; failed database writes are retried instead
+#[derive(Debug)]
+enum DaemonError {
+    Db(rusqlite::Error),
+    Io(io::Error),
+    Nix(nix::Error),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Db(err) => write!(f, "database: {}", err),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Nix(err) => write!(f, "{}", err),
         }
     }
-    println!("RECORD({:?}, {:?}): {}", subsystem, device, message);
-    Some(()) // XXX
+}
+
+impl Error for DaemonError {}
+
+impl From<rusqlite::Error> for DaemonError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Db(err)
+    }
+}
+
+impl From<io::Error> for DaemonError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<nix::Error> for DaemonError {
+    fn from(err: nix::Error) -> Self {
+        Self::Nix(err)
+    }
+}
+
+// Writes that haven't reached the database yet. If the database is locked or
+// otherwise failing, these are kept in memory and retried with backoff.
+#[derive(Default)]
+struct PendingWrites {
+    queue: VecDeque<TelemetryEvent>,
+    state: Option<Vec<TelemetryEvent>>,
+    crash_dumps: Option<HashSet<i64>>,
+    temps: VecDeque<util::Temps>,
+    fans: VecDeque<util::Fan>,
+    // Counts of data dropped over `Limits`, by kind
+    dropped: HashMap<&'static str, usize>,
+    // Events dropped from the full queue, already rolled back in the state
+    // table, to roll back in memory too
+    rolled_back: Vec<TelemetryEvent>,
+    backoff: Option<Duration>,
+    retry_time: Option<Instant>,
+}
+
+impl PendingWrites {
+    fn queue(&mut self, event: TelemetryEvent) {
+        self.queue.push_back(event);
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len() + self.temps.len() + self.fans.len()
+    }
+
+    fn count_dropped(&mut self, kind: &'static str, count: usize) {
+        eprintln!("Dropped {} {} over limit", count, kind);
+        *self.dropped.entry(kind).or_default() += count;
+    }
+
+    // Drop the oldest writes over the limit, samples before events
+    fn limit(&mut self) {
+        let max = util::hp_vendor_conf().limits.pending;
+        let mut count = 0;
+        while self.len() > max {
+            if self.temps.pop_front().is_none() && self.fans.pop_front().is_none() {
+                self.queue.pop_front();
+            }
+            count += 1;
+        }
+        if count > 0 {
+            self.count_dropped("pending", count);
+        }
+    }
+
+    // Each write is removed once it succeeds, so nothing is written twice
+    fn write(&mut self, db: &DB) -> rusqlite::Result<()> {
+        let limits = &util::hp_vendor_conf().limits;
+        if !self.queue.is_empty() {
+            let mut insert_statement = db.prepare_queue_insert()?;
+            while let Some(event) = self.queue.front() {
+                insert_statement.execute(event)?;
+                self.queue.pop_front();
+            }
+        }
+        if let Some(state) = &self.state {
+            db.replace_state(db::State::Frequency(SamplingFrequency::OnChange), state)?;
+            self.state = None;
+        }
+        if let Some(crash_dumps) = &self.crash_dumps {
+            db.add_crash_dumps(crash_dumps)?;
+            self.crash_dumps = None;
+        }
+        let sampled = !self.temps.is_empty() || !self.fans.is_empty();
+        while let Some(temps) = self.temps.front() {
+            db.insert_temps(temps)?;
+            self.temps.pop_front();
+        }
+        while let Some(fan) = self.fans.front() {
+            db.insert_fan(fan)?;
+            self.fans.pop_front();
+        }
+        if sampled {
+            let count = db.limit_samples(limits.samples)?;
+            if count > 0 {
+                eprintln!("Dropped {} samples over limit", count);
+            }
+        }
+        // After the state is written, so rollback isn't overwritten. Also
+        // covers events queued by daily collection.
+        let (count, rolled_back) = db.limit_queued(limits.queued_events)?;
+        if count > 0 {
+            eprintln!("Dropped {} queued events over limit", count);
+        }
+        self.rolled_back.extend(rolled_back);
+        while let Some((&kind, &count)) = self.dropped.iter().next() {
+            db.add_dropped(kind, count)?;
+            self.dropped.remove(kind);
+        }
+        Ok(())
+    }
+
+    // Write everything, unless still waiting to retry a failure
+    fn flush(&mut self, db: &DB) {
+        self.limit();
+        if self.retry_time.map_or(false, |x| Instant::now() < x) {
+            return;
+        }
+        match self.write(db) {
+            Ok(()) => {
+                if self.backoff.is_some() {
+                    eprintln!("Database writes succeeded after retrying");
+                }
+                self.backoff = None;
+                self.retry_time = None;
+            }
+            Err(err) => {
+                let backoff = self.backoff.map_or(RETRY_MIN, |x| (x * 2).min(RETRY_MAX));
+                eprintln!(
+                    "Error: Failed to write to database, retrying in {}s with {} writes pending: {}",
+                    backoff.as_secs(),
+                    self.len(),
+                    err
+                );
+                self.backoff = Some(backoff);
+                self.retry_time = Some(Instant::now() + backoff);
+            }
+        }
+    }
+
+    // Wake up for the next retry, even if nothing else happens
+    fn timeout(&self) -> Option<Duration> {
+        self.retry_time
+            .map(|x| x.saturating_duration_since(Instant::now()))
+    }
+}
+
+// Udev events are applied once a device has had none for this long
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Latest udev event of each device, until `DEBOUNCE` passes without another,
+// so devices that bounce on a dock or flaky hub only queue their net change.
+// Generic over the device only so it can be tested without udev.
+struct Debouncer<D = udev::Device>(HashMap<PathBuf, (Instant, udev::EventType, D)>);
+
+impl<D> Default for Debouncer<D> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl Debouncer {
+    fn push(&mut self, event: &udev::Event) {
+        let (event_type, device) = match drm_connector_card(event) {
+            Some(card) => (udev::EventType::Change, card),
+            None => (event.event_type(), event.device()),
+        };
+        let syspath = device.syspath().to_owned();
+        self.push_at(Instant::now(), syspath, event_type, device);
+    }
+
+    // Events of devices whose window has passed
+    fn settled(&mut self) -> Vec<(udev::EventType, udev::Device)> {
+        self.settled_at(Instant::now())
+    }
+}
+
+impl<D: Clone> Debouncer<D> {
+    fn push_at(
+        &mut self,
+        now: Instant,
+        syspath: PathBuf,
+        mut event_type: udev::EventType,
+        device: D,
+    ) {
+        // A change doesn't replace an add that hasn't been applied yet
+        if let Some((_, udev::EventType::Add, _)) = self.0.get(&syspath) {
+            if event_type == udev::EventType::Change {
+                event_type = udev::EventType::Add;
+            }
+        }
+        self.0.insert(syspath, (now + DEBOUNCE, event_type, device));
+    }
+
+    fn settled_at(&mut self, now: Instant) -> Vec<(udev::EventType, D)> {
+        let mut settled = Vec::new();
+        self.0.retain(|_, (deadline, event_type, device)| {
+            if *deadline > now {
+                return true;
+            }
+            settled.push((*event_type, device.clone()));
+            false
+        });
+        settled
+    }
+
+    // Wake up when the next window passes
+    fn timeout(&self) -> Option<Duration> {
+        self.0
+            .values()
+            .map(|(deadline, _, _)| deadline.saturating_duration_since(Instant::now()))
+            .min()
+    }
+}
+
+// Audio jacks report insertion with input events, rather than uevents
+struct Jacks {
+    files: HashMap<Token, (File, PathBuf)>,
+    next_token: usize,
+}
+
+impl Jacks {
+    fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            next_token: TOKEN_JACK.0,
+        }
+    }
+
+    fn add(&mut self, poll: &mio::Poll, device: &udev::Device) {
+        if !util::input::is_jack(device) {
+            return;
+        }
+        let file = match device.devnode().and_then(|x| {
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(x)
+                .ok()
+        }) {
+            Some(file) => file,
+            None => {
+                return;
+            }
+        };
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        if let Err(err) = poll.registry().register(
+            &mut SourceFd(&file.as_raw_fd()),
+            token,
+            mio::Interest::READABLE,
+        ) {
+            eprintln!(
+                "Error: Failed to watch '{}': {}",
+                device.syspath().display(),
+                err
+            );
+            return;
+        }
+        self.files
+            .insert(token, (file, device.syspath().to_owned()));
+    }
+
+    fn clear(&mut self, poll: &mio::Poll) {
+        for (file, _) in self.files.values() {
+            let _ = poll.registry().deregister(&mut SourceFd(&file.as_raw_fd()));
+        }
+        self.files.clear();
+    }
+
+    fn remove(&mut self, poll: &mio::Poll, device: &udev::Device) {
+        self.files.retain(|_, (file, syspath)| {
+            if syspath.as_path() == device.syspath() {
+                let _ = poll.registry().deregister(&mut SourceFd(&file.as_raw_fd()));
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+// Arm timer for thermal sampling every `seconds`
+fn set_sample_timer(timer: &TimerFd, seconds: i64) -> nix::Result<()> {
+    timer.set(
+        Expiration::Interval(TimeSpec::from_duration(Duration::from_secs(seconds as u64))),
+        TimerSetTimeFlags::empty(),
+    )
+}
+
+// Arm timer for daily collection at `time`, which fires immediately if it has
+// already passed
+fn set_daily_timer(timer: &TimerFd, time: i64) -> nix::Result<()> {
+    timer.set(
+        Expiration::OneShot(TimeSpec::from_duration(Duration::from_secs(
+            time.max(1) as u64
+        ))),
+        TimerSetTimeFlags::TFD_TIMER_ABSTIME,
+    )
+}
+
+// Run daily collection a day after it last ran, including by the timer in
+// previous boots
+fn schedule_daily(db: &DB, timer: &TimerFd) -> Result<(), DaemonError> {
+    let last_time = db.get_last_daily_time()?;
+    set_daily_timer(timer, (last_time + time::Duration::DAY).unix_timestamp())?;
+    Ok(())
+}
+
+// Collection that reads from devices, run off the main loop
+enum Collection {
+    Daily,
+    Triggers(Vec<Trigger>),
+}
+
+// Runs collection in a thread with its own connection, so the main loop keeps
+// handling events. `waker` is woken when it finishes.
+fn spawn_collection(
+    collection: Collection,
+    waker: Arc<mio::Waker>,
+) -> thread::JoinHandle<rusqlite::Result<()>> {
+    thread::spawn(move || {
+        let res = match collection {
+            // Skip if `hp-vendor daily` is running; it will update the last time
+            Collection::Daily => match util::lock::try_lock_file(daily::LOCK) {
+                Some(_lock) => DB::open().and_then(|db| daily::collect(&db)),
+                None => Ok(()),
+            },
+            Collection::Triggers(triggers) => DB::open().and_then(|db| {
+                let freqs = db.get_event_frequencies()?;
+                for trigger in triggers {
+                    collect_trigger(&db, &freqs, trigger);
+                }
+                Ok(())
+            }),
+        };
+        let _ = waker.wake();
+        res
+    })
+}
+
+fn join_collection(thread: thread::JoinHandle<rusqlite::Result<()>>) -> Result<(), DaemonError> {
+    match thread.join() {
+        Ok(res) => res.map_err(DaemonError::from),
+        Err(_) => Err(DaemonError::Io(io::Error::new(
+            ErrorKind::Other,
+            "collection thread panicked",
+        ))),
+    }
+}
+
+// Re-arms the timer after daily collection, to retry sooner if it failed.
+// Errors are logged rather than stopping the daemon.
+fn finish_daily(db: &DB, timer: &TimerFd, thread: thread::JoinHandle<rusqlite::Result<()>>) {
+    let res = join_collection(thread);
+    if let Err(err) = res.and_then(|()| schedule_daily(db, timer)) {
+        eprintln!("Error: Daily collection failed: {}", err);
+        let time = time::OffsetDateTime::now_utc() + DAILY_RETRY;
+        if let Err(err) = set_daily_timer(timer, time.unix_timestamp()) {
+            eprintln!("Error: Failed to set daily timer: {}", err);
+        }
+    }
+}
+
+// Replaces a D-Bus monitor whose `busctl` process exited, like when the bus
+// restarts, so its events aren't silently missed from then on
+fn restart_monitor<T: AsRawFd>(
+    poll: &mio::Poll,
+    token: Token,
+    name: &str,
+    monitor: &mut Option<T>,
+    exited: fn(&T) -> Option<Duration>,
+    new: fn() -> io::Result<T>,
+) -> io::Result<()> {
+    let uptime = match monitor.as_ref().and_then(exited) {
+        Some(uptime) => uptime,
+        None => {
+            return Ok(());
+        }
+    };
+    if let Some(old) = monitor.take() {
+        poll.registry()
+            .deregister(&mut SourceFd(&old.as_raw_fd()))?;
+    }
+    if uptime < MONITOR_MIN_UPTIME {
+        eprintln!(
+            "Error: Monitor for {} exited after {:?}; not restarting",
+            name, uptime
+        );
+        return Ok(());
+    }
+    eprintln!("Monitor for {} exited; restarting", name);
+    match new() {
+        Ok(new) => {
+            poll.registry().register(
+                &mut SourceFd(&new.as_raw_fd()),
+                token,
+                mio::Interest::READABLE,
+            )?;
+            *monitor = Some(new);
+        }
+        Err(err) => eprintln!("Error: Failed to restart monitor for {}: {}", name, err),
+    }
+    Ok(())
+}
+
+// AC adapter plugged in or removed, or dock connected
+fn udev_trigger(event: &udev::Event) -> Option<Trigger> {
+    match (
+        event.subsystem().and_then(OsStr::to_str),
+        event.event_type(),
+    ) {
+        (Some("power_supply"), udev::EventType::Change)
+            if event.attribute_value("type") == Some(OsStr::new("Mains")) =>
+        {
+            Some(Trigger::AcChange)
+        }
+        (Some("thunderbolt"), udev::EventType::Add)
+            if event.devtype() == Some(OsStr::new("thunderbolt_device")) =>
+        {
+            Some(Trigger::Dock)
+        }
+        _ => None,
+    }
+}
+
+// Connectors like `card0-DP-1`, including ones added and removed with a DP MST
+// hub, are children of the card `HwDisplay` is collected from
+fn drm_connector_card(device: &udev::Device) -> Option<udev::Device> {
+    if device.subsystem().and_then(OsStr::to_str) != Some("drm")
+        || !device.sysname().to_str()?.contains('-')
+    {
+        return None;
+    }
+    device
+        .parent()
+        .filter(|x| x.subsystem().and_then(OsStr::to_str) == Some("drm"))
+}
+
+fn collect_trigger(db: &DB, freqs: &Frequencies, trigger: Trigger) {
+    if let Err(err) = crate::update_trigger_events_and_queue(db, freqs, trigger) {
+        eprintln!("Error: Failed to collect on trigger {:?}: {}", trigger, err);
+    }
+}
+
+// Watches for the boot to finish, or `None` if it already has or can't be
+// watched. Started before checking, so the signal isn't missed in between.
+fn watch_startup(poll: &mio::Poll) -> io::Result<Option<util::boot::StartupMonitor>> {
+    let startup_monitor = match util::boot::StartupMonitor::new() {
+        Ok(startup_monitor) => startup_monitor,
+        Err(err) => {
+            eprintln!("Error: Failed to monitor startup: {}", err);
+            return Ok(None);
+        }
+    };
+    if util::boot::boot_finished() != Some(false) {
+        return Ok(None);
+    }
+    poll.registry().register(
+        &mut SourceFd(&startup_monitor.as_raw_fd()),
+        TOKEN_STARTUP,
+        mio::Interest::READABLE,
+    )?;
+    Ok(Some(startup_monitor))
+}
+
+fn add_trigger(triggers: &mut Vec<Trigger>, trigger: Trigger) {
+    if !triggers.contains(&trigger) {
+        triggers.push(trigger);
+    }
+}
+
+fn on_change(freqs: &Frequencies, type_: TelemetryEventType) -> bool {
+    freqs.get(type_) == SamplingFrequency::OnChange
+}
+
+fn udev_descs_for<I: Iterator<Item = TelemetryEventType>>(types: I) -> UdevDescs {
+    let mut udev_descs = UdevDescs::new();
+    for i in types {
+        if let Some(crate::EventDesc::Udev(desc)) = crate::event(i) {
+            udev_descs.insert(desc);
+        }
+    }
+    udev_descs
+}
+
+fn collect_sim_cards() -> Vec<TelemetryEvent> {
+    let mut events = Vec::new();
+    if let Some(crate::EventDesc::Periodic(desc)) =
+        crate::event(TelemetryEventType::HwPeripheralSimCard)
+    {
+        desc.generate(&mut events);
+    }
+    events
+}
+
+// Collect SIM cards again, and queue any difference from `sim_cards`
+fn update_sim_cards(pending: &mut PendingWrites, sim_cards: &mut Vec<TelemetryEvent>) {
+    let new = collect_sim_cards();
+    let mut diff = new.clone();
+    event::diff(&mut diff, sim_cards);
+    for event in diff {
+        pending.queue(event);
+    }
+    *sim_cards = new;
+}
+
+// Watches for SIM changes, only if collected on change and there is a modem to
+// have one
+fn watch_sims(
+    poll: &mio::Poll,
+    freqs: &Frequencies,
+) -> io::Result<Option<util::modem::SimMonitor>> {
+    if !on_change(freqs, TelemetryEventType::HwPeripheralSimCard)
+        || util::modem::wwan_devices().is_empty()
+    {
+        return Ok(None);
+    }
+    match util::modem::SimMonitor::new() {
+        Ok(sim_monitor) => {
+            poll.registry().register(
+                &mut SourceFd(&sim_monitor.as_raw_fd()),
+                TOKEN_SIM,
+                mio::Interest::READABLE,
+            )?;
+            Ok(Some(sim_monitor))
+        }
+        Err(err) => {
+            eprintln!("Error: Failed to monitor SIM: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+// Apply frequencies changed in the database, only rescanning for types that
+// became `OnChange`. Types that are no longer `OnChange` keep their state for
+// `hp-vendor daily` to diff against.
+fn reload_frequencies(
+    db: &DB,
+    poll: &mio::Poll,
+    freqs: &mut Frequencies,
+    udev_descs: &mut UdevDescs,
+    jacks: &mut Jacks,
+    udev_devices: &mut HashMap<PathBuf, Vec<TelemetryEvent>>,
+    sim_monitor: &mut Option<util::modem::SimMonitor>,
+    sim_cards: &mut Vec<TelemetryEvent>,
+    pending: &mut PendingWrites,
+) -> Result<(), DaemonError> {
+    let new_freqs = db.get_event_frequencies()?;
+    let added = TelemetryEventType::iter()
+        .filter(|x| !on_change(freqs, *x) && on_change(&new_freqs, *x))
+        .collect::<Vec<_>>();
+    let mut old = Vec::new();
+    for type_ in &added {
+        old.extend(db.get_state(db::State::Type(*type_))?);
+    }
+
+    for events in udev_devices.values_mut() {
+        events.retain(|x| on_change(&new_freqs, x.type_()));
+    }
+    udev_devices.retain(|_, events| !events.is_empty());
+    if !on_change(&new_freqs, TelemetryEventType::HwPeripheralAudioPort) {
+        jacks.clear(poll);
+    }
+    if !on_change(&new_freqs, TelemetryEventType::HwPeripheralSimCard) {
+        if let Some(sim_monitor) = sim_monitor.take() {
+            poll.registry()
+                .deregister(&mut SourceFd(&sim_monitor.as_raw_fd()))?;
+        }
+        sim_cards.clear();
+    }
+
+    let added_descs = udev_descs_for(added.iter().copied());
+    let watch_jacks = added.contains(&TelemetryEventType::HwPeripheralAudioPort);
+    let mut new = Vec::new();
+    let mut enumerator = udev::Enumerator::new()?;
+    for device in enumerator.scan_devices()? {
+        if watch_jacks {
+            jacks.add(poll, &device);
+        }
+        let mut events = Vec::new();
+        added_descs.generate(&mut events, &device);
+        if !events.is_empty()
+            && track_device(pending, udev_devices, device.syspath(), events.clone())
+        {
+            new.extend(events);
+        }
+    }
+    if added.contains(&TelemetryEventType::HwPeripheralSimCard) {
+        *sim_monitor = watch_sims(poll, &new_freqs)?;
+        *sim_cards = collect_sim_cards();
+        new.extend(sim_cards.iter().cloned());
+    }
+
+    let mut diff = new;
+    event::diff(&mut diff, &old);
+    for event in diff {
+        pending.queue(event);
+    }
+
+    *udev_descs = udev_descs_for(TelemetryEventType::iter().filter(|x| on_change(&new_freqs, *x)));
+    *freqs = new_freqs;
+    Ok(())
+}
+
+// Keep events of a device to diff against on change or removal. Past the
+// limit, new devices aren't tracked, and their events are dropped.
+fn track_device(
+    pending: &mut PendingWrites,
+    udev_devices: &mut HashMap<PathBuf, Vec<TelemetryEvent>>,
+    syspath: &Path,
+    events: Vec<TelemetryEvent>,
+) -> bool {
+    if !udev_devices.contains_key(syspath)
+        && udev_devices.len() >= util::hp_vendor_conf().limits.udev_devices
+    {
+        pending.count_dropped("udev_devices", 1);
+        return false;
+    }
+    udev_devices
+        .entry(syspath.to_owned())
+        .or_default()
+        .extend(events);
+    true
+}
+
+// Regenerate events for a device, and queue any difference from its old
+// state. A device starts or stops being tracked as it starts or stops matching
+// a collector, like a card when a connector is plugged in. Returns whether it
+// was tracked before or after.
+fn update_device(
+    pending: &mut PendingWrites,
+    udev_descs: &UdevDescs,
+    udev_devices: &mut HashMap<PathBuf, Vec<TelemetryEvent>>,
+    device: &udev::Device,
+) -> bool {
+    let old = udev_devices.remove(device.syspath()).unwrap_or_default();
+    let mut new = Vec::new();
+    udev_descs.generate(&mut new, device);
+    if !new.is_empty() && !track_device(pending, udev_devices, device.syspath(), new.clone()) {
+        return false;
+    }
+    let tracked = !old.is_empty() || !new.is_empty();
+    let mut diff = new;
+    event::diff(&mut diff, &old);
+    for event in diff {
+        pending.queue(event);
+    }
+    tracked
+}
+
+// Queue removal of a device's events, returning whether it was tracked
+fn remove_device(
+    pending: &mut PendingWrites,
+    udev_devices: &mut HashMap<PathBuf, Vec<TelemetryEvent>>,
+    syspath: &Path,
+) -> bool {
+    let events = match udev_devices.remove(syspath) {
+        Some(events) => events,
+        None => {
+            return false;
+        }
+    };
+    for event in events {
+        if let Some(remove_event) = crate::event::remove_event(event) {
+            pending.queue(remove_event);
+        }
+    }
+    true
+}
+
+// Regenerate events for every device, since uevents are missed while
+// suspended, and queue removal of devices that are gone
+fn rescan_devices(
+    poll: &mio::Poll,
+    freqs: &Frequencies,
+    udev_descs: &UdevDescs,
+    jacks: &mut Jacks,
+    udev_devices: &mut HashMap<PathBuf, Vec<TelemetryEvent>>,
+    pending: &mut PendingWrites,
+) -> io::Result<()> {
+    let watch_jacks = on_change(freqs, TelemetryEventType::HwPeripheralAudioPort);
+    jacks.clear(poll);
+    let mut syspaths = HashSet::new();
+    let mut enumerator = udev::Enumerator::new()?;
+    for device in enumerator.scan_devices()? {
+        if watch_jacks {
+            jacks.add(poll, &device);
+        }
+        update_device(pending, udev_descs, udev_devices, &device);
+        syspaths.insert(device.syspath().to_owned());
+    }
+    udev_devices.retain(|syspath, events| {
+        if syspaths.contains(syspath) {
+            return true;
+        }
+        for event in events.drain(..) {
+            if let Some(remove_event) = crate::event::remove_event(event) {
+                pending.queue(remove_event);
+            }
+        }
+        false
+    });
+    Ok(())
+}
+
+// https://www.kernel.org/doc/Documentation/ABI/testing/dev-kmsg
+fn parse_kmsg(buf: &[u8]) -> Option<(u32, u64, &str)> {
+    let record = str::from_utf8(buf).ok()?;
+    let (props, message) = record.lines().next()?.split_once(';')?;
+
+    // Priority, sequence number, timestamp, and flags
+    let mut props = props.split(',');
+    let prio = props.next()?.parse().ok()?;
+    let _seq = props.next()?;
+    let timestamp = props.next()?.parse().ok()?;
+
+    Some((prio, timestamp, message))
 }
 
 pub fn run() {
+    if let Err(err) = run_daemon() {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run_daemon() -> Result<(), DaemonError> {
     // Get unique lock
-    let _lock = util::lock::lock_file_or_panic("/var/hp-vendor/daemon.lock");
+    let _lock = util::lock::lock_file_or_panic(LOCK);
 
-    let db = DB::open().unwrap();
+    let db = DB::open()?;
     crate::exit_if_not_opted_in(&db);
-    let mut insert_statement = db.prepare_queue_insert().unwrap();
 
-    let mut poll = mio::Poll::new().unwrap();
+    let mut poll = mio::Poll::new()?;
 
     // Register polling for signals
     let mut mask = SigSet::empty();
     mask.add(signal::SIGTERM);
-    mask.thread_block().unwrap();
-    let signal = SignalFd::new(&mask).unwrap();
-    poll.registry()
-        .register(
-            &mut SourceFd(&signal.as_raw_fd()),
-            TOKEN_SIGNAL,
-            mio::Interest::READABLE,
-        )
-        .unwrap();
+    mask.add(signal::SIGHUP);
+    mask.thread_block()?;
+    let mut signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)?;
+    poll.registry().register(
+        &mut SourceFd(&signal_fd.as_raw_fd()),
+        TOKEN_SIGNAL,
+        mio::Interest::READABLE,
+    )?;
 
     // Register polling for udev usb events
-    let mut socket = udev::MonitorBuilder::new().unwrap().listen().unwrap();
-    poll.registry()
-        .register(
-            &mut socket,
-            TOKEN_UDEV,
-            mio::Interest::READABLE | mio::Interest::WRITABLE,
-        )
-        .unwrap();
+    let mut socket = udev::MonitorBuilder::new()?.listen()?;
+    poll.registry().register(
+        &mut socket,
+        TOKEN_UDEV,
+        mio::Interest::READABLE | mio::Interest::WRITABLE,
+    )?;
 
     // Register polling for kmsg/dmesg events
     let mut kmsg_file = OpenOptions::new()
         .read(true)
         .custom_flags(libc::O_NONBLOCK)
-        .open("/dev/kmsg")
-        .unwrap();
-    kmsg_file.seek(SeekFrom::End(0)).unwrap();
-    poll.registry()
-        .register(
-            &mut SourceFd(&kmsg_file.as_raw_fd()),
-            TOKEN_KMSG,
-            mio::Interest::READABLE,
-        )
-        .unwrap();
+        .open("/dev/kmsg")?;
+    kmsg_file.seek(SeekFrom::End(0))?;
+    poll.registry().register(
+        &mut SourceFd(&kmsg_file.as_raw_fd()),
+        TOKEN_KMSG,
+        mio::Interest::READABLE,
+    )?;
 
     // Register polling for a timer, for thermal sampling
-    let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::empty()).unwrap();
-    timer
-        .set(
-            Expiration::Interval(TimeSpec::from_duration(Duration::from_secs(
-                util::TEMP_SAMPLE_SECONDS as u64,
-            ))),
-            TimerSetTimeFlags::empty(),
-        )
-        .unwrap();
-    poll.registry()
-        .register(
-            &mut mio::unix::SourceFd(&timer.as_raw_fd()),
-            TOKEN_TIMER,
-            mio::Interest::READABLE,
-        )
-        .unwrap();
+    let mut sample_intervals = util::SampleIntervals::new(&db.get_sampling()?);
+    let mut sample_interval = sample_intervals.next(None);
+    // Counts time suspended, so it fires on resume if it expired
+    let timer = TimerFd::new(ClockId::CLOCK_BOOTTIME, TimerFlags::empty())?;
+    set_sample_timer(&timer, sample_interval)?;
+    poll.registry().register(
+        &mut mio::unix::SourceFd(&timer.as_raw_fd()),
+        TOKEN_TIMER,
+        mio::Interest::READABLE,
+    )?;
 
-    let freqs = db.get_event_frequencies().unwrap();
+    // Register polling for a timer, for daily and weekly collection
+    let daily_timer = TimerFd::new(ClockId::CLOCK_REALTIME, TimerFlags::empty())?;
+    schedule_daily(&db, &daily_timer)?;
+    poll.registry().register(
+        &mut SourceFd(&daily_timer.as_raw_fd()),
+        TOKEN_DAILY,
+        mio::Interest::READABLE,
+    )?;
+    let collect_waker = Arc::new(mio::Waker::new(poll.registry(), TOKEN_COLLECT_DONE)?);
+    // Thread running collection, and if it's the daily one. Only one runs at
+    // a time, with the rest waiting in `daily_due` and `triggers`.
+    let mut collect_thread = None;
+    let mut daily_due = false;
+    let mut triggers = Vec::new();
 
-    let mut udev_descs = crate::UdevDescs::new();
-    for i in event::TelemetryEventType::iter() {
-        if freqs.get(i) != SamplingFrequency::OnChange {
-            continue;
-        } else if let Some(crate::EventDesc::Udev(desc)) = crate::event(i) {
-            udev_descs.insert(desc);
-        }
-    }
+    let mut freqs = db.get_event_frequencies()?;
+    let mut udev_descs =
+        udev_descs_for(TelemetryEventType::iter().filter(|x| on_change(&freqs, *x)));
+    let mut jacks = Jacks::new();
+
+    let old = db.get_state(db::State::Frequency(SamplingFrequency::OnChange))?;
 
-    let old = db
-        .get_state(db::State::Frequency(SamplingFrequency::OnChange))
-        .unwrap();
+    let mut pending = PendingWrites::default();
+
+    // Started before collecting, so a change in between isn't missed
+    let mut sim_monitor = watch_sims(&poll, &freqs)?;
+    let mut sim_cards = if on_change(&freqs, TelemetryEventType::HwPeripheralSimCard) {
+        collect_sim_cards()
+    } else {
+        Vec::new()
+    };
 
     let mut new = Vec::new();
-    let mut enumerator = udev::Enumerator::new().unwrap();
-    let mut udev_event_idx = Vec::new();
-    for device in enumerator.scan_devices().unwrap() {
+    let mut udev_devices = HashMap::new();
+    let mut enumerator = udev::Enumerator::new()?;
+    let watch_jacks = on_change(&freqs, TelemetryEventType::HwPeripheralAudioPort);
+    for device in enumerator.scan_devices()? {
+        if watch_jacks {
+            jacks.add(&poll, &device);
+        }
         let mut events = Vec::new();
         udev_descs.generate(&mut events, &device);
-        if !events.is_empty() {
-            new.extend_from_slice(&events);
-            udev_event_idx.push((
-                device.syspath().to_owned(),
-                (new.len() - events.len())..new.len(),
-            ));
+        if !events.is_empty()
+            && track_device(
+                &mut pending,
+                &mut udev_devices,
+                device.syspath(),
+                events.clone(),
+            )
+        {
+            new.extend(events);
         }
     }
+    new.extend(sim_cards.iter().cloned());
 
     let mut diff = new.clone();
     event::diff(&mut diff, &old);
     for event in diff {
-        insert_statement.execute(&event).unwrap();
-        println!("{:#?}", event);
+        pending.queue(event);
+    }
+    pending.state = Some(new);
+
+    // Crashes from previous boots, not seen by the kmsg reader
+    let reported_dumps = db.get_crash_dumps()?;
+    let mut dumps = HashSet::new();
+    for dump in util::crash::crash_dumps() {
+        // The same record may be both in pstore and archived by `systemd-pstore`
+        if !reported_dumps.contains(&dump.hash) && dumps.insert(dump.hash) {
+            pending.queue(dump.event().into());
+        }
+    }
+    pending.crash_dumps = Some(dumps);
+
+    pending.flush(&db);
+
+    // Not on restarts of the daemon in the same boot. Once the boot finishes,
+    // so its performance can be read.
+    let mut startup_monitor = None;
+    if let Some(boot_id) = util::boot::boot_id() {
+        match db.update_last_boot_id(&boot_id) {
+            Ok(true) => {
+                startup_monitor = watch_startup(&poll)?;
+                if startup_monitor.is_none() {
+                    add_trigger(&mut triggers, Trigger::Boot);
+                }
+            }
+            Ok(false) => {}
+            Err(err) => eprintln!("Error: Failed to update boot ID: {}", err),
+        }
     }
-    let ids = db
-        .replace_state(db::State::Frequency(SamplingFrequency::OnChange), &new)
-        .unwrap();
-    let mut udev_devices = udev_event_idx
-        .into_iter()
-        .map(|(syspath, range)| (syspath, ids[range].to_owned()))
-        .collect::<HashMap<_, _>>();
+
+    let mut crash_parser = util::crash::CrashParser::new();
+
+    // Register polling for suspend and resume
+    let mut sleep_monitor = match util::logind::SleepMonitor::new() {
+        Ok(sleep_monitor) => {
+            poll.registry().register(
+                &mut SourceFd(&sleep_monitor.as_raw_fd()),
+                TOKEN_SLEEP,
+                mio::Interest::READABLE,
+            )?;
+            Some(sleep_monitor)
+        }
+        Err(err) => {
+            eprintln!("Error: Failed to monitor suspend: {}", err);
+            None
+        }
+    };
+    let new_inhibitor = || match util::logind::SleepInhibitor::new() {
+        Ok(inhibitor) => Some(inhibitor),
+        Err(err) => {
+            eprintln!("Error: Failed to inhibit suspend: {}", err);
+            None
+        }
+    };
+    // Held until suspend, to flush writes first
+    let mut _inhibitor = sleep_monitor.as_ref().and_then(|_| new_inhibitor());
 
     let mut sensors = util::Sensors::new();
     if sensors.is_none() {
         eprintln!("Error: Failed to intitialize `Sensors`");
     }
 
+    let mut debouncer: Debouncer = Debouncer::default();
+    // Removals dropped from the full queue, kept in the state until restart
+    let mut removed_devices = Vec::new();
     let mut events = mio::Events::with_capacity(1024);
     loop {
-        loop {
-            match poll.poll(&mut events, None) {
-                Ok(()) => {
-                    break;
-                }
-                Err(err) => {
-                    if err.kind() != ErrorKind::Interrupted {
-                        panic!("Mio polling error {}", err);
-                    }
-                }
+        if collect_thread.is_none() {
+            if mem::take(&mut daily_due) {
+                let thread = spawn_collection(Collection::Daily, collect_waker.clone());
+                collect_thread = Some((true, thread));
+            } else if !triggers.is_empty() {
+                let collection = Collection::Triggers(mem::take(&mut triggers));
+                let thread = spawn_collection(collection, collect_waker.clone());
+                collect_thread = Some((false, thread));
             }
         }
 
+        let timeout = pending
+            .timeout()
+            .into_iter()
+            .chain(debouncer.timeout())
+            .min();
+        if let Err(err) = poll.poll(&mut events, timeout) {
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            pending.flush(&db);
+            return Err(err.into());
+        }
+
+        let mut state_changed = false;
         for event in &events {
             match event.token() {
                 TOKEN_SIGNAL => {
-                    println!("SIGTERM");
-                    return;
+                    while let Ok(Some(info)) = signal_fd.read_signal() {
+                        if info.ssi_signo == signal::SIGHUP as u32 {
+                            println!("SIGHUP");
+                            if let Err(err) = reload_frequencies(
+                                &db,
+                                &poll,
+                                &mut freqs,
+                                &mut udev_descs,
+                                &mut jacks,
+                                &mut udev_devices,
+                                &mut sim_monitor,
+                                &mut sim_cards,
+                                &mut pending,
+                            ) {
+                                eprintln!("Error: Failed to reload frequencies: {}", err);
+                            }
+                            match db.get_sampling() {
+                                Ok(sampling) => {
+                                    sample_intervals = util::SampleIntervals::new(&sampling);
+                                    sample_interval = sample_intervals.next(None);
+                                    set_sample_timer(&timer, sample_interval)?;
+                                }
+                                Err(err) => {
+                                    eprintln!("Error: Failed to reload sampling config: {}", err)
+                                }
+                            }
+                            state_changed = true;
+                            continue;
+                        }
+
+                        println!("SIGTERM");
+                        // Last attempt, ignoring the backoff
+                        pending.retry_time = None;
+                        pending.flush(&db);
+                        if pending.backoff.is_some() {
+                            eprintln!("Error: Exiting with {} writes pending", pending.len());
+                        }
+                        return Ok(());
+                    }
                 }
                 TOKEN_UDEV => {
                     socket.clone().for_each(|x| {
+                        if let Some(trigger) = udev_trigger(&x) {
+                            add_trigger(&mut triggers, trigger);
+                        }
                         if x.event_type() == udev::EventType::Add {
-                            let mut events = Vec::new();
-                            udev_descs.generate(&mut events, &x);
-                            for event in &events {
-                                println!("{:#?}", event);
-                                insert_statement.execute(event).unwrap();
-                            }
-                            if !events.is_empty() {
-                                let ids = db.replace_state(db::State::Ids(&[]), &events).unwrap();
-                                udev_devices.insert(x.syspath().to_owned(), ids);
-                            } else {
-                                udev_devices.remove(x.syspath());
+                            if on_change(&freqs, TelemetryEventType::HwPeripheralAudioPort) {
+                                jacks.add(&poll, &x);
                             }
                         } else if x.event_type() == udev::EventType::Remove {
-                            if let Some(ids) = udev_devices.remove(x.syspath()) {
-                                let events = db.get_state(db::State::Ids(&ids)).unwrap();
-                                for event in events {
-                                    if let Some(remove_event) = crate::event::remove_event(event) {
-                                        println!("{:#?}", remove_event);
-                                        insert_statement.execute(&remove_event).unwrap();
-                                    }
-                                }
-                                db.replace_state(db::State::Ids(&ids), &[]).unwrap();
-                            }
-                        } else if x.event_type() == udev::EventType::Change {
-                            if let Some(ids) = udev_devices.remove(x.syspath()) {
-                                let old = db.get_state(db::State::Ids(&ids)).unwrap();
-                                let mut new = Vec::new();
-                                udev_descs.generate(&mut new, &x);
-                                let mut diff = new.clone();
-                                event::diff(&mut diff, &old);
-                                for event in diff {
-                                    insert_statement.execute(&event).unwrap();
-                                    println!("{:#?}", event);
-                                }
-                                if !events.is_empty() {
-                                    let ids = db.replace_state(db::State::Ids(&ids), &new).unwrap();
-                                    udev_devices.insert(x.syspath().to_owned(), ids);
-                                }
-                            }
+                            jacks.remove(&poll, &x);
                         }
+                        debouncer.push(&x);
                     });
                 }
                 TOKEN_KMSG => {
-                    let mut buf = [0; 1024];
-                    while let Ok(len) = unistd::read(kmsg_file.as_raw_fd(), &mut buf) {
-                        parse_kmsg(&buf[..len]);
+                    let mut buf = [0; 8192];
+                    loop {
+                        let len = match unistd::read(kmsg_file.as_raw_fd(), &mut buf) {
+                            Ok(len) => len,
+                            // Records were overwritten before being read
+                            Err(Errno::EPIPE) => {
+                                continue;
+                            }
+                            Err(_) => {
+                                break;
+                            }
+                        };
+                        if let Some((prio, timestamp, message)) = parse_kmsg(&buf[..len]) {
+                            // Ignore messages written from userspace
+                            if prio >> 3 != 0 {
+                                continue;
+                            }
+                            let mut crashes = Vec::new();
+                            crash_parser.push(timestamp, message, &mut crashes);
+                            for crash in crashes {
+                                pending.queue(crash.into());
+                                add_trigger(&mut triggers, Trigger::KernelWarning);
+                            }
+                        }
                     }
                 }
                 TOKEN_TIMER => {
@@ -247,17 +1108,212 @@ pub fn run() {
                     let _ = unistd::read(timer.as_raw_fd(), &mut buf);
                     if let Some(sensors) = &mut sensors {
                         sensors.update();
-                        if let Some(rpm) = sensors.fan() {
-                            // println!("Fan: {} RPM", rpm);
+                        if let Some(fan) = sensors.fan() {
+                            // println!("Fan: {} RPM", fan.rpm);
+                            pending.fans.push_back(fan);
                         }
                         if let Some(temps) = sensors.thermal() {
                             // println!("Temps: {:?}", temps);
-                            db.insert_temps(&temps).unwrap();
+                            // Sample faster on battery or under thermal stress
+                            let interval = sample_intervals.next(Some(&temps));
+                            if interval != sample_interval {
+                                set_sample_timer(&timer, interval)?;
+                                sample_interval = interval;
+                            }
+                            pending.temps.push_back(temps);
+                        }
+                    }
+                }
+                TOKEN_SLEEP => {
+                    let signals = match &mut sleep_monitor {
+                        Some(sleep_monitor) => sleep_monitor.read(),
+                        None => Vec::new(),
+                    };
+                    for start in signals {
+                        if start {
+                            println!("Suspending");
+                            // Stop sampling; the `SampleClock` of the next
+                            // sample shows the gap
+                            timer.unset()?;
+                            pending.retry_time = None;
+                            pending.flush(&db);
+                            // Allow suspend to continue
+                            _inhibitor = None;
+                        } else {
+                            println!("Resumed");
+                            _inhibitor = new_inhibitor();
+                            set_sample_timer(&timer, sample_interval)?;
+                            if let Err(err) = rescan_devices(
+                                &poll,
+                                &freqs,
+                                &udev_descs,
+                                &mut jacks,
+                                &mut udev_devices,
+                                &mut pending,
+                            ) {
+                                eprintln!("Error: Failed to rescan devices: {}", err);
+                            }
+                            // SIMs may have changed while suspended
+                            if on_change(&freqs, TelemetryEventType::HwPeripheralSimCard) {
+                                update_sim_cards(&mut pending, &mut sim_cards);
+                            }
+                            state_changed = true;
+                            add_trigger(&mut triggers, Trigger::Resume);
+                        }
+                    }
+                    restart_monitor(
+                        &poll,
+                        TOKEN_SLEEP,
+                        "suspend",
+                        &mut sleep_monitor,
+                        util::logind::SleepMonitor::exited,
+                        util::logind::SleepMonitor::new,
+                    )?;
+                    if sleep_monitor.is_none() {
+                        // Would only delay suspend, without being released
+                        _inhibitor = None;
+                    }
+                }
+                TOKEN_DAILY => {
+                    let mut buf = [0; 8];
+                    let _ = unistd::read(daily_timer.as_raw_fd(), &mut buf);
+                    // Summarizes the samples written by the daemon
+                    pending.flush(&db);
+                    daily_due = true;
+                }
+                TOKEN_SIM => {
+                    if sim_monitor.as_mut().map_or(false, |x| x.read()) {
+                        update_sim_cards(&mut pending, &mut sim_cards);
+                        state_changed = true;
+                    }
+                    restart_monitor(
+                        &poll,
+                        TOKEN_SIM,
+                        "SIM",
+                        &mut sim_monitor,
+                        util::modem::SimMonitor::exited,
+                        util::modem::SimMonitor::new,
+                    )?;
+                }
+                TOKEN_STARTUP => {
+                    let finished = startup_monitor.as_mut().map_or(false, |x| x.read());
+                    // If it can't be watched anymore, collect without waiting
+                    let exited = startup_monitor
+                        .as_ref()
+                        .map_or(false, |x| x.exited().is_some());
+                    if finished || exited {
+                        if let Some(startup_monitor) = startup_monitor.take() {
+                            poll.registry()
+                                .deregister(&mut SourceFd(&startup_monitor.as_raw_fd()))?;
+                        }
+                        add_trigger(&mut triggers, Trigger::Boot);
+                    }
+                }
+                TOKEN_COLLECT_DONE => match collect_thread.take() {
+                    Some((true, thread)) => finish_daily(&db, &daily_timer, thread),
+                    Some((false, thread)) => {
+                        if let Err(err) = join_collection(thread) {
+                            eprintln!("Error: Trigger collection failed: {}", err);
+                        }
+                    }
+                    None => {}
+                },
+                token => {
+                    // Removed earlier in this batch, or by a reload or rescan
+                    let (file, syspath) = match jacks.files.get(&token) {
+                        Some(jack) => jack,
+                        None => {
+                            continue;
+                        }
+                    };
+                    // Read all pending input events; the state is queried after
+                    let mut buf = [0; 1024];
+                    while let Ok(len) = unistd::read(file.as_raw_fd(), &mut buf) {
+                        if len == 0 {
+                            break;
+                        }
+                    }
+                    if let Ok(device) = udev::Device::from_syspath(syspath) {
+                        if update_device(&mut pending, &udev_descs, &mut udev_devices, &device) {
+                            state_changed = true;
                         }
                     }
                 }
-                _ => unreachable!(),
             }
         }
+
+        // Diffed against the tracked state, so events that cancel out within
+        // the window queue nothing
+        for (event_type, device) in debouncer.settled() {
+            match event_type {
+                udev::EventType::Add | udev::EventType::Change => {
+                    if update_device(&mut pending, &udev_descs, &mut udev_devices, &device) {
+                        state_changed = true;
+                    }
+                }
+                udev::EventType::Remove => {
+                    if remove_device(&mut pending, &mut udev_devices, device.syspath()) {
+                        state_changed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if state_changed {
+            pending.state = Some(
+                udev_devices
+                    .values()
+                    .flatten()
+                    .chain(&sim_cards)
+                    .chain(&removed_devices)
+                    .cloned()
+                    .collect(),
+            );
+        }
+        pending.flush(&db);
+        // Devices are diffed again on their next change, and removals on the
+        // next start of the daemon, which diffs against the state table
+        for event in mem::take(&mut pending.rolled_back) {
+            if on_change(&freqs, event.type_()) {
+                for events in udev_devices.values_mut() {
+                    event::forget(events, &event);
+                }
+                event::forget(&mut sim_cards, &event);
+                event::rollback(&mut removed_devices, &event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce_burst() {
+        let mut debouncer = Debouncer::default();
+        let start = Instant::now();
+        let syspath = PathBuf::from("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1");
+
+        // Bouncing on a flaky hub, each within `DEBOUNCE` of the last
+        let events = [
+            udev::EventType::Add,
+            udev::EventType::Remove,
+            udev::EventType::Add,
+            udev::EventType::Change,
+        ];
+        for (n, event_type) in events.iter().enumerate() {
+            let now = start + DEBOUNCE / 2 * n as u32;
+            debouncer.push_at(now, syspath.clone(), *event_type, ());
+        }
+
+        // Only flushed once `DEBOUNCE` passes after the last event, as the
+        // net change
+        let last = start + DEBOUNCE / 2 * 3;
+        assert!(debouncer.settled_at(last + DEBOUNCE / 2).is_empty());
+        let settled = debouncer.settled_at(last + DEBOUNCE);
+        assert!(matches!(settled[..], [(udev::EventType::Add, ())]));
+        assert!(debouncer.settled_at(last + DEBOUNCE * 2).is_empty());
     }
 }
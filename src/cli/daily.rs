@@ -2,36 +2,77 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use time::OffsetDateTime;
+
 use crate::{config::SamplingFrequency, db::DB, util};
 
+pub const LOCK: &str = "/var/hp-vendor/daily.lock";
+
+/// Collects daily and weekly events, and summarizes samples from the daemon.
+/// Run by the daemon, or by `hp-vendor-daily.timer` if it isn't running.
+pub fn collect(db: &DB) -> rusqlite::Result<()> {
+    let freqs = db.get_event_frequencies()?;
+
+    crate::update_events_and_queue(db, &freqs, SamplingFrequency::Daily)?;
+    if db.last_weekly_time_expired()? {
+        crate::update_events_and_queue(db, &freqs, SamplingFrequency::Weekly)?;
+        db.update_last_weekly_time()?;
+    }
+
+    let mut insert_statement = db.prepare_queue_insert()?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    // Summarize each complete window, starting from the oldest sample
+    while let Some(start) = db.get_first_temps_time()? {
+        let end = start + util::SUMMARY_SECONDS;
+        if end > now {
+            break;
+        }
+        let temps = db.get_temps(Some(end))?;
+        // Samples of a window without a zone the server requires are
+        // dropped, rather than held forever
+        if let Some(summary) = util::sumarize_temps(&temps) {
+            insert_statement.execute(&summary.into())?;
+        }
+        if let Some(battery_life) = util::sumarize_battery_life(&temps) {
+            insert_statement.execute(&battery_life.into())?;
+        }
+        db.remove_temps_before(temps.last().unwrap())?;
+    }
+
+    let fans = db.get_fans()?;
+    if let Some((summary, last)) = util::sumarize_fan_cycles(&fans) {
+        insert_statement.execute(&summary.into())?;
+        db.remove_fans_before(last.time)?;
+    } else {
+        // Too few cycles yet, but don't keep samples forever if the fan
+        // rarely cycles
+        db.remove_fans_before(now - util::MAX_FAN_SAMPLE_AGE)?;
+    }
+
+    db.update_last_daily_time()
+}
+
 pub fn run() {
     // Get unique lock
-    let _lock = util::lock::lock_file_or_panic("/var/hp-vendor/daily.lock");
+    let _lock = util::lock::lock_file_or_panic(LOCK);
 
     // XXX handle db errors?
     let db = DB::open().unwrap();
     crate::exit_if_not_opted_in(&db);
 
-    let freqs = db.get_event_frequencies().unwrap();
-
-    crate::update_events_and_queue(&db, &freqs, SamplingFrequency::Daily).unwrap();
-    if db.last_weekly_time_expired().unwrap() {
-        crate::update_events_and_queue(&db, &freqs, SamplingFrequency::Weekly).unwrap();
-        db.update_last_weekly_time().unwrap();
+    // The daemon schedules collection itself
+    if util::lock::is_locked(super::daemon::LOCK) {
+        eprintln!("Daemon is running, so not collecting from timer.");
+        return;
     }
 
-    let mut insert_statement = db.prepare_queue_insert().unwrap();
-    loop {
-        let temps = db.get_temps(true).unwrap();
-        if temps.len() < 100 {
-            break;
-        }
-        insert_statement
-            .execute(&util::sumarize_temps(&temps).into())
-            .unwrap();
-        if let Some(battery_life) = util::sumarize_battery_life(&temps) {
-            insert_statement.execute(&battery_life.into()).unwrap();
-        }
-        db.remove_temps_before(temps.last().unwrap()).unwrap();
+    collect(&db).unwrap();
+
+    // The daemon does this itself, so it can roll back state it holds
+    let (count, _) = db
+        .limit_queued(util::hp_vendor_conf().limits.queued_events)
+        .unwrap();
+    if count > 0 {
+        eprintln!("Dropped {} queued events over limit", count);
     }
 }
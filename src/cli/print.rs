@@ -21,13 +21,16 @@ pub fn run(mut args: env::Args) {
 
     match args.next().as_deref() {
         Some("consent") => println!("{:#?}", db.get_consent().unwrap()),
+        Some("dropped") => println!("{:#?}", db.get_dropped().unwrap()),
         Some("frequencies") => println!("{:#?}", db.get_event_frequencies().unwrap()),
         Some("purposes") => println!("{:#?}", crate::purposes(&db, api(&db).as_ref())),
         Some("queued") => println!("{:#?}", db.get_queued().unwrap().1),
         Some("state") => println!("{:#?}", db.get_state(db::State::All).unwrap()),
-        Some("temps") => println!("{:#?}", db.get_temps(false).unwrap()),
+        Some("temps") => println!("{:#?}", db.get_temps(None).unwrap()),
         _ => {
-            eprintln!("Usage: hp-vendor print (consent|frequencies|purposes|queued|state|temps)");
+            eprintln!(
+                "Usage: hp-vendor print (consent|dropped|frequencies|purposes|queued|state|temps)"
+            );
             process::exit(1);
         }
     }
@@ -55,10 +55,18 @@ pub fn run(mut args: env::Args) {
             Ok(config) => {
                 let frequencies = db.get_event_frequencies().unwrap();
                 let new_frequencies = config.frequencies();
+                let mut changed = false;
                 if frequencies != new_frequencies {
                     db.set_event_frequencies(new_frequencies).unwrap();
-                    eprintln!("Config changed. Restarting daemon...");
-                    util::systemd::try_restart_daemon();
+                    changed = true;
+                }
+                if &db.get_sampling().unwrap() != config.sampling() {
+                    db.set_sampling(config.sampling()).unwrap();
+                    changed = true;
+                }
+                if changed {
+                    eprintln!("Config changed. Reloading daemon...");
+                    util::systemd::try_reload_daemon();
                 }
             }
             Err(err) => eprintln!("Error getting frequencies from server: {}", err),
@@ -5,7 +5,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use crate::{event::TelemetryEventType, frequency::Frequencies};
+use crate::{event::TelemetryEventType, frequency::Frequencies, util::SamplingConf};
 
 schemafy::schemafy!("DataConfigResponseModel.json");
 
@@ -32,6 +32,9 @@ pub struct Config {
     #[allow(dead_code)]
     sampling_frequency: HashMap<Type, Freq>,
     // sampling_frequency: SamplingFrequencyModel,
+    // Not in the model yet, so optional
+    #[serde(default)]
+    thermal_sampling: SamplingConf,
 }
 
 impl Config {
@@ -46,4 +49,8 @@ impl Config {
         });
         Frequencies::from_iter_or_default(iter)
     }
+
+    pub fn sampling(&self) -> &SamplingConf {
+        &self.thermal_sampling
+    }
 }
@@ -5,14 +5,20 @@
 use rusqlite::{
     params,
     types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Value, ValueRef},
-    Connection, OptionalExtension, Result, Statement,
+    Connection, OptionalExtension, Result, Statement, Transaction, TransactionBehavior,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, str,
 };
-use std::{collections::HashMap, error::Error, fmt, str};
 use time::{Duration, OffsetDateTime};
 
 use crate::{
     config::SamplingFrequency,
-    event::{DataCollectionConsent, DataCollectionPurpose, TelemetryEvent, TelemetryEventType},
+    event::{
+        self, DataCollectionConsent, DataCollectionPurpose, TelemetryEvent, TelemetryEventType,
+    },
     frequency::Frequencies,
     util,
 };
@@ -20,8 +26,8 @@ use crate::{
 pub enum State<'a> {
     All,
     Frequency(SamplingFrequency),
-    #[allow(dead_code)]
     Type(TelemetryEventType),
+    #[allow(dead_code)]
     Ids(&'a [i64]),
 }
 
@@ -88,14 +94,155 @@ fn migration2(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
-static MIGRATIONS: &[fn(&Connection) -> Result<()>] = &[migration1, migration2];
+fn migration3(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE crash_dumps (
+             path TEXT NOT NULL,
+             time INTEGER NOT NULL,
+             PRIMARY KEY (path, time)
+        );",
+    )?;
+    Ok(())
+}
+
+fn migration4(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE fans (
+             id INTEGER PRIMARY KEY,
+             rpm INTEGER NOT NULL,
+             time INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+fn migration5(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE properties ADD COLUMN last_daily_time INTEGER;")?;
+    Ok(())
+}
+
+fn migration6(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE properties ADD COLUMN last_boot_id TEXT;")?;
+    Ok(())
+}
+
+// Allow zones without a sensor
+fn migration7(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE temps_new (
+             id INTEGER PRIMARY KEY,
+             cpu INTEGER,
+             ext INTEGER,
+             bat INTEGER,
+             chg INTEGER,
+             on_ac INTEGER NOT NULL,
+             charging INTEGER NOT NULL,
+             time INTEGER NOT NULL
+        );
+        INSERT INTO temps_new SELECT * FROM temps;
+        DROP TABLE temps;
+        ALTER TABLE temps_new RENAME TO temps;",
+    )?;
+    Ok(())
+}
+
+// Move readings to a table with a row per zone, so new zones don't need a
+// migration
+fn migration8(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE zone_temps (
+             temps_id INTEGER NOT NULL,
+             zone TEXT NOT NULL,
+             value INTEGER NOT NULL,
+             PRIMARY KEY (temps_id, zone)
+        );
+        INSERT INTO zone_temps SELECT id, 'cpu', cpu FROM temps WHERE cpu IS NOT NULL;
+        INSERT INTO zone_temps SELECT id, 'ext', ext FROM temps WHERE ext IS NOT NULL;
+        INSERT INTO zone_temps SELECT id, 'bat', bat FROM temps WHERE bat IS NOT NULL;
+        INSERT INTO zone_temps SELECT id, 'chg', chg FROM temps WHERE chg IS NOT NULL;
+        CREATE TABLE temps_new (
+             id INTEGER PRIMARY KEY,
+             on_ac INTEGER NOT NULL,
+             charging INTEGER NOT NULL,
+             time INTEGER NOT NULL
+        );
+        INSERT INTO temps_new SELECT id, on_ac, charging, time FROM temps;
+        DROP TABLE temps;
+        ALTER TABLE temps_new RENAME TO temps;",
+    )?;
+    Ok(())
+}
+
+// Clocks to measure up time between samples
+fn migration9(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE temps ADD COLUMN boot_id TEXT;
+        ALTER TABLE temps ADD COLUMN monotonic INTEGER;
+        ALTER TABLE temps ADD COLUMN boottime INTEGER;
+        ALTER TABLE fans ADD COLUMN boot_id TEXT;
+        ALTER TABLE fans ADD COLUMN monotonic INTEGER;
+        ALTER TABLE fans ADD COLUMN boottime INTEGER;",
+    )?;
+    Ok(())
+}
+
+// Sampling config from the server, as JSON
+fn migration10(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE properties ADD COLUMN sampling TEXT;")?;
+    Ok(())
+}
+
+// Counts of data dropped over `Limits`, by kind
+fn migration11(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE dropped (
+             kind TEXT NOT NULL PRIMARY KEY,
+             count INTEGER NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+// Identify crash dumps by content, since `systemd-pstore` moves them. Dumps
+// recorded by path can't be matched, so may be reported again once.
+fn migration12(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DROP TABLE crash_dumps;
+        CREATE TABLE crash_dumps (
+             hash INTEGER NOT NULL PRIMARY KEY
+        );",
+    )?;
+    Ok(())
+}
+
+static MIGRATIONS: &[fn(&Connection) -> Result<()>] = &[
+    migration1,
+    migration2,
+    migration3,
+    migration4,
+    migration5,
+    migration6,
+    migration7,
+    migration8,
+    migration9,
+    migration10,
+    migration11,
+    migration12,
+];
 
 pub struct DB(Connection);
 
 impl DB {
     pub fn open() -> Result<Self> {
-        let conn = Connection::open("/var/hp-vendor/db.sqlite3")?;
+        Self::from_connection(Connection::open("/var/hp-vendor/db.sqlite3")?)
+    }
 
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
         let tx = conn.unchecked_transaction()?;
         let user_version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
         for migration in &MIGRATIONS[user_version..] {
@@ -111,6 +258,23 @@ impl DB {
         Ok(db)
     }
 
+    /// Starts a transaction that takes the write lock right away, so a read
+    /// and write of the state can't interleave with another connection's, like
+    /// the daemon's collection thread. Methods called in it don't start their
+    /// own.
+    pub fn transaction(&self) -> Result<Transaction> {
+        Transaction::new_unchecked(&self.0, TransactionBehavior::Immediate)
+    }
+
+    // Like `transaction`, but `None` if already in one
+    fn nested_transaction(&self) -> Result<Option<Transaction>> {
+        if self.0.is_autocommit() {
+            self.transaction().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn prepare_queue_insert(&self) -> Result<QueueInsert> {
         self.0
             .prepare(
@@ -220,6 +384,50 @@ impl DB {
             .map(|_| ())
     }
 
+    pub fn get_last_daily_time(&self) -> Result<OffsetDateTime> {
+        let time: Option<i64> =
+            self.0
+                .query_row("SELECT last_daily_time from properties", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(OffsetDateTime::from_unix_timestamp(time.unwrap_or(0))
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH))
+    }
+
+    pub fn update_last_daily_time(&self) -> Result<()> {
+        let time = OffsetDateTime::now_utc().unix_timestamp();
+        self.0
+            .execute("UPDATE properties SET last_daily_time = ?", [time])
+            .map(|_| ())
+    }
+
+    /// Stores `boot_id`, returning if it differs from the last one stored
+    pub fn update_last_boot_id(&self, boot_id: &str) -> Result<bool> {
+        let last_boot_id: Option<String> =
+            self.0
+                .query_row("SELECT last_boot_id from properties", [], |row| row.get(0))?;
+        self.0
+            .execute("UPDATE properties SET last_boot_id = ?", [boot_id])?;
+        Ok(last_boot_id.as_deref() != Some(boot_id))
+    }
+
+    // Default if never set, or invalid
+    pub fn get_sampling(&self) -> Result<util::SamplingConf> {
+        let sampling: Option<String> =
+            self.0
+                .query_row("SELECT sampling from properties", [], |row| row.get(0))?;
+        Ok(sampling
+            .and_then(|x| serde_json::from_str(&x).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_sampling(&self, sampling: &util::SamplingConf) -> Result<()> {
+        let sampling = serde_json::to_string(sampling).unwrap();
+        self.0
+            .execute("UPDATE properties SET sampling = ?", [sampling])
+            .map(|_| ())
+    }
+
     fn init_event_types(&self) -> Result<()> {
         // Add with default frequency if not already in db
         let mut insert_statement = self.0.prepare(
@@ -298,7 +506,7 @@ impl DB {
              VALUES (?, ?)",
         )?;
 
-        let tx = self.0.unchecked_transaction()?;
+        let tx = self.nested_transaction()?;
         match filter {
             State::All => {
                 self.0.execute("DELETE from state", [])?;
@@ -330,7 +538,9 @@ impl DB {
             insert_statement.execute(params!(i.type_().name(), i))?;
             ids.push(self.0.last_insert_rowid());
         }
-        tx.commit()?;
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
         Ok(ids)
     }
 
@@ -346,11 +556,62 @@ impl DB {
 
     pub fn remove_queued(&self, ids: &[i64]) -> Result<()> {
         let mut stmt = self.0.prepare("DELETE from queued_events where id = ?")?;
-        let tx = self.0.unchecked_transaction()?;
+        let tx = self.nested_transaction()?;
         for id in ids {
             stmt.execute([id])?;
         }
-        tx.commit()
+        match tx {
+            Some(tx) => tx.commit(),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops queued events over `max`, returning how many, and the dropped
+    /// events that were rolled back in the state table
+    pub fn limit_queued(&self, max: usize) -> Result<(usize, Vec<TelemetryEvent>)> {
+        let tx = self.transaction()?;
+        let count: i64 = self
+            .0
+            .query_row("SELECT COUNT(*) FROM queued_events", [], |row| row.get(0))?;
+        if count as usize <= max {
+            return Ok((0, Vec::new()));
+        }
+        let (ids, events) = self.get_queued()?;
+        let queued = ids.into_iter().zip(events).collect::<Vec<_>>();
+        let (ids, rollback) = event::compact(&queued, max, &self.get_event_frequencies()?);
+
+        // Roll back first, so an event is at worst queued again, not lost
+        if !rollback.is_empty() {
+            let mut state = self.get_state(State::All)?;
+            for event in &rollback {
+                event::rollback(&mut state, event);
+            }
+            self.replace_state(State::All, &state)?;
+        }
+        self.remove_queued(&ids)?;
+        self.add_dropped("queued_events", ids.len())?;
+        tx.commit()?;
+        Ok((ids.len(), rollback))
+    }
+
+    pub fn add_dropped(&self, kind: &str, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.0.execute(
+            "INSERT INTO dropped (kind, count)
+             VALUES (?, ?)
+             ON CONFLICT(kind) DO
+                 UPDATE SET count=count + excluded.count",
+            params![kind, count as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_dropped(&self) -> Result<HashMap<String, i64>> {
+        let mut stmt = self.0.prepare("SELECT kind, count FROM dropped")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.filter_map(Result::ok).collect())
     }
 
     pub fn delete_and_disable(&self) -> Result<()> {
@@ -365,54 +626,163 @@ impl DB {
     }
 
     pub fn insert_temps(&self, temps: &util::Temps) -> Result<()> {
+        let tx = self.0.unchecked_transaction()?;
         self.0.execute(
-            "INSERT INTO temps (cpu, ext, bat, chg, on_ac, charging, time)
-             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            "INSERT INTO temps (on_ac, charging, time, boot_id, monotonic, boottime)
+             VALUES (?, ?, ?, ?, ?, ?)",
             params![
-                temps.cpu,
-                temps.ext,
-                temps.bat,
-                temps.chg,
                 temps.on_ac,
                 temps.charging,
                 temps.time,
+                temps.clock.boot_id,
+                temps.clock.monotonic,
+                temps.clock.boottime
+            ],
+        )?;
+        let temps_id = self.0.last_insert_rowid();
+        let mut insert_statement = self.0.prepare(
+            "INSERT INTO zone_temps (temps_id, zone, value)
+             VALUES (?, ?, ?)",
+        )?;
+        for (zone, value) in &temps.zones {
+            insert_statement.execute(params![temps_id, zone, value])?;
+        }
+        tx.commit()
+    }
+
+    pub fn get_first_temps_time(&self) -> Result<Option<i64>> {
+        self.0
+            .query_row("SELECT MIN(time) FROM temps", [], |row| row.get(0))
+    }
+
+    // Samples with time less than `end`, or all
+    pub fn get_temps(&self, end: Option<i64>) -> Result<Vec<util::Temps>> {
+        let mut stmt = self.0.prepare(
+            "SELECT id, on_ac, charging, time, boot_id, monotonic, boottime FROM temps
+             WHERE ?1 IS NULL OR time < ?1
+             ORDER BY time",
+        )?;
+        let mut zones_stmt = self
+            .0
+            .prepare("SELECT zone, value FROM zone_temps WHERE temps_id = ?")?;
+        let rows = stmt.query_map([end], |row| {
+            let temps_id: i64 = row.get(0)?;
+            let zones = zones_stmt
+                .query_map([temps_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_>>()?;
+            Ok(util::Temps {
+                zones,
+                on_ac: row.get(1)?,
+                charging: row.get(2)?,
+                time: row.get(3)?,
+                clock: util::SampleClock {
+                    boot_id: row.get(4)?,
+                    monotonic: row.get(5)?,
+                    boottime: row.get(6)?,
+                },
+            })
+        })?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
+    pub fn insert_fan(&self, fan: &util::Fan) -> Result<()> {
+        self.0.execute(
+            "INSERT INTO fans (rpm, time, boot_id, monotonic, boottime)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                fan.rpm,
+                fan.time,
+                fan.clock.boot_id,
+                fan.clock.monotonic,
+                fan.clock.boottime
             ],
         )?;
         Ok(())
     }
 
-    pub fn get_temps(&self, limit: bool) -> Result<Vec<util::Temps>> {
-        let mut stmt = if limit {
-            self.0.prepare(
-                "SELECT cpu, ext, bat, chg, on_ac, charging, time FROM temps
-                 ORDER BY time
-                 LIMIT 100",
-            )?
-        } else {
-            self.0.prepare(
-                "SELECT cpu, ext, bat, chg, on_ac, charging, time FROM temps
-                 ORDER BY time",
-            )?
-        };
+    pub fn get_fans(&self) -> Result<Vec<util::Fan>> {
+        let mut stmt = self.0.prepare(
+            "SELECT rpm, time, boot_id, monotonic, boottime FROM fans
+             ORDER BY time",
+        )?;
         let rows = stmt.query_map([], |row| {
-            Ok(util::Temps {
-                cpu: row.get(0)?,
-                ext: row.get(1)?,
-                bat: row.get(2)?,
-                chg: row.get(3)?,
-                on_ac: row.get(4)?,
-                charging: row.get(5)?,
-                time: row.get(6)?,
+            Ok(util::Fan {
+                rpm: row.get(0)?,
+                time: row.get(1)?,
+                clock: util::SampleClock {
+                    boot_id: row.get(2)?,
+                    monotonic: row.get(3)?,
+                    boottime: row.get(4)?,
+                },
             })
         })?;
         Ok(rows.filter_map(Result::ok).collect())
     }
 
+    // Remove where time less than `time`, so a sample at that time may start
+    // the next cycle
+    pub fn remove_fans_before(&self, time: i64) -> Result<()> {
+        self.0.execute("DELETE FROM fans WHERE time < ?", [time])?;
+        Ok(())
+    }
+
+    // Crash dumps that have already been queued, by hash of their content
+    pub fn get_crash_dumps(&self) -> Result<HashSet<i64>> {
+        let mut stmt = self.0.prepare("SELECT hash FROM crash_dumps")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.filter_map(Result::ok).collect())
+    }
+
+    // Never removed, since a dump may reappear after `systemd-pstore` moves it
+    pub fn add_crash_dumps(&self, dumps: &HashSet<i64>) -> Result<()> {
+        let tx = self.0.unchecked_transaction()?;
+        let mut stmt = self.0.prepare(
+            "INSERT OR IGNORE INTO crash_dumps (hash)
+             VALUES (?)",
+        )?;
+        for hash in dumps {
+            stmt.execute([hash])?;
+        }
+        tx.commit()
+    }
+
+    /// Drops the oldest thermal and fan samples over `max` of each, returning
+    /// how many
+    pub fn limit_samples(&self, max: usize) -> Result<usize> {
+        let max = max as i64;
+        let tx = self.0.unchecked_transaction()?;
+        self.0.execute(
+            "DELETE FROM zone_temps WHERE temps_id IN
+                 (SELECT id FROM temps ORDER BY time DESC LIMIT -1 OFFSET ?)",
+            [max],
+        )?;
+        let temps = self.0.execute(
+            "DELETE FROM temps WHERE id IN
+                 (SELECT id FROM temps ORDER BY time DESC LIMIT -1 OFFSET ?)",
+            [max],
+        )?;
+        let fans = self.0.execute(
+            "DELETE FROM fans WHERE id IN
+                 (SELECT id FROM fans ORDER BY time DESC LIMIT -1 OFFSET ?)",
+            [max],
+        )?;
+        self.add_dropped("temps", temps)?;
+        self.add_dropped("fans", fans)?;
+        tx.commit()?;
+        Ok(temps + fans)
+    }
+
     // Remove where time less than last
     pub fn remove_temps_before(&self, temps: &util::Temps) -> Result<()> {
+        let tx = self.0.unchecked_transaction()?;
+        self.0.execute(
+            "DELETE FROM zone_temps WHERE temps_id IN
+                 (SELECT id FROM temps WHERE time <= ?)",
+            [temps.time],
+        )?;
         self.0
             .execute("DELETE FROM temps WHERE time <= ?", [temps.time])?;
-        Ok(())
+        tx.commit()
     }
 }
 
@@ -492,3 +862,76 @@ impl FromSql for SamplingFrequency {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::PeripheralSIMCard;
+
+    fn sim(id: &str, state: event::State) -> TelemetryEvent {
+        PeripheralSIMCard {
+            sim_id: Some(id.to_string()),
+            state,
+        }
+        .into()
+    }
+
+    #[test]
+    fn limit_queued() {
+        let db = DB::open_in_memory().unwrap();
+        let state = vec![sim("a", event::State::Added), sim("b", event::State::Added)];
+        db.replace_state(State::All, &state).unwrap();
+        let mut insert_statement = db.prepare_queue_insert().unwrap();
+        for event in &state {
+            insert_statement.execute(event).unwrap();
+        }
+        insert_statement
+            .execute(&sim("c", event::State::Removed))
+            .unwrap();
+
+        // Under the limit
+        assert_eq!(db.limit_queued(3).unwrap(), (0, Vec::new()));
+
+        // The oldest are dropped, and rolled back in the state
+        let (count, rollback) = db.limit_queued(1).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(rollback, state);
+        assert_eq!(
+            db.get_queued().unwrap().1,
+            vec![sim("c", event::State::Removed)]
+        );
+        assert_eq!(db.get_dropped().unwrap().get("queued_events"), Some(&2));
+
+        // So diffing against it queues them again
+        let mut events = state.clone();
+        event::diff(&mut events, &db.get_state(State::All).unwrap());
+        assert_eq!(events, state);
+    }
+
+    #[test]
+    fn remove_old_fans() {
+        let db = DB::open_in_memory().unwrap();
+        let now = 10 * util::MAX_FAN_SAMPLE_AGE;
+        let week_ago = now - util::MAX_FAN_SAMPLE_AGE;
+        for time in [week_ago - 60, week_ago, now - 60] {
+            let fan = util::Fan {
+                rpm: 1000,
+                time,
+                clock: util::SampleClock::default(),
+            };
+            db.insert_fan(&fan).unwrap();
+        }
+
+        // Too few cycles to summarize, so only samples over a week old go
+        let fans = db.get_fans().unwrap();
+        assert!(util::sumarize_fan_cycles(&fans).is_none());
+        db.remove_fans_before(week_ago).unwrap();
+        let times = db
+            .get_fans()
+            .unwrap()
+            .iter()
+            .map(|x| x.time)
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![week_ago, now - 60]);
+    }
+}
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use os_release::OsRelease;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::{
     collections::{HashMap, HashSet},
     fs,
@@ -13,11 +13,17 @@ use std::{
 use time::{format_description::well_known::Rfc3339, OffsetDateTime, Time};
 use uuid::Uuid;
 
-use crate::util::dmi::{dmi, SystemInfo24};
+use crate::{
+    config::SamplingFrequency,
+    frequency::Frequencies,
+    util::dmi::{dmi, SystemInfo24},
+};
 
 pub use hp_vendor_client::{DataCollectionConsent, DataCollectionPurpose};
 
-schemafy::schemafy!("DataUploadRequestModel.json");
+// Generated from `DataUploadRequestModel.json`, with local fields added by
+// `build.rs`
+include!(concat!(env!("OUT_DIR"), "/event_types.rs"));
 
 // Unlike genereated binding, an `enum` rather than a `struct`
 include!(concat!(env!("OUT_DIR"), "/event_enum.rs"));
@@ -112,8 +118,21 @@ fn data_header(consents: Vec<String>, ids: DeviceOSIds) -> TelemetryHeaderModel
     }
 }
 
+// Without local fields, which the server rejects
+fn serialize_uploaded<S: Serializer>(
+    data: &[TelemetryEvent],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(data.iter().map(|event| {
+        let mut event = event.clone();
+        event.clear_local();
+        event
+    }))
+}
+
 #[derive(Debug, Serialize)]
 pub struct Events<'a> {
+    #[serde(serialize_with = "serialize_uploaded")]
     pub data: &'a [TelemetryEvent],
     pub data_header: TelemetryHeaderModel,
 }
@@ -151,6 +170,99 @@ pub fn remove_event(mut event: TelemetryEvent) -> Option<TelemetryEvent> {
     Some(event)
 }
 
+// Lower is dropped first from a full queue. Summaries are periodic, and
+// inventory can be rolled back in the state table to be diffed again, but
+// crashes and boot performance can't be collected again.
+fn priority(freqs: &Frequencies, type_: TelemetryEventType) -> u8 {
+    use TelemetryEventType::*;
+
+    match type_ {
+        HwBatteryLife | HwCoolingFanCyclesSummary | HwNvmeSmartLog | HwThermalSummary => 0,
+        SwBootPerformance | SwLinuxDriverCrash => 3,
+        // State is held by the daemon, so only rolled back in the database
+        _ if freqs.get(type_) == SamplingFrequency::OnChange => 2,
+        _ => 1,
+    }
+}
+
+fn has_state(event: &TelemetryEvent) -> bool {
+    event.clone().state_mut().is_some()
+}
+
+/// Queued events to drop so at most `max` remain: ids of all of them, and
+/// the events that need `rollback` in the state. First drops devices added
+/// then removed again while queued, then the oldest events of the lowest
+/// priority.
+pub fn compact(
+    queued: &[(i64, TelemetryEvent)],
+    max: usize,
+    freqs: &Frequencies,
+) -> (Vec<i64>, Vec<TelemetryEvent>) {
+    if queued.len() <= max {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut queued = queued.iter().collect::<Vec<_>>();
+    queued.sort_by_key(|(id, _)| *id);
+
+    // Indices of the events since each device was added
+    let mut added = HashMap::<_, Vec<usize>>::new();
+    let mut cancelled = HashSet::new();
+    for (n, (_, event)) in queued.iter().enumerate() {
+        let key = (event.type_(), event.primaries());
+        match event.clone().state_mut() {
+            Some(State::Added) => {
+                added.insert(key, vec![n]);
+            }
+            Some(State::Updated) => {
+                if let Some(indices) = added.get_mut(&key) {
+                    indices.push(n);
+                }
+            }
+            Some(State::Removed) => {
+                if let Some(indices) = added.remove(&key) {
+                    cancelled.extend(indices);
+                    cancelled.insert(n);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut ids = cancelled.iter().map(|n| queued[*n].0).collect::<Vec<_>>();
+    let mut rollback = Vec::new();
+    let mut remaining = (0..queued.len())
+        .filter(|n| !cancelled.contains(n))
+        .collect::<Vec<_>>();
+    if remaining.len() > max {
+        remaining.sort_by_key(|n| priority(freqs, queued[*n].1.type_()));
+        for n in &remaining[..remaining.len() - max] {
+            let (id, event) = queued[*n];
+            ids.push(*id);
+            if has_state(event) {
+                rollback.push(event.clone());
+            }
+        }
+    }
+
+    (ids, rollback)
+}
+
+/// Removes any event with the same primary keys as `event` from `state`
+pub fn forget(state: &mut Vec<TelemetryEvent>, event: &TelemetryEvent) {
+    let key = (event.type_(), event.primaries());
+    state.retain(|x| (x.type_(), x.primaries()) != key);
+}
+
+/// Undoes a dropped event in `state`, so the next diff queues it again.
+/// Added or updated events are forgotten, and removed ones restored.
+pub fn rollback(state: &mut Vec<TelemetryEvent>, dropped: &TelemetryEvent) {
+    forget(state, dropped);
+    if let Some(State::Removed) = dropped.clone().state_mut() {
+        state.push(dropped.clone());
+    }
+}
+
 pub fn diff(events: &mut Vec<TelemetryEvent>, old_events: &[TelemetryEvent]) {
     // TODO: warn if multiple things have same primary key?
 
@@ -197,3 +309,80 @@ pub fn diff(events: &mut Vec<TelemetryEvent>, old_events: &[TelemetryEvent]) {
     });
     events.extend(new_events);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sim(id: &str, state: State) -> TelemetryEvent {
+        PeripheralSIMCard {
+            sim_id: Some(id.to_string()),
+            state,
+        }
+        .into()
+    }
+
+    fn battery_life() -> TelemetryEvent {
+        BatteryLife {
+            ct_number: "CT".to_string(),
+            cycle_count: 1,
+            energy_full: 50000,
+            serial_number: "1".to_string(),
+            timestamp: date_time(),
+            total_ac_charging_time: None,
+            total_ac_time: 60,
+            total_dc_time: 0,
+        }
+        .into()
+    }
+
+    #[test]
+    fn compact_by_priority() {
+        let freqs = Frequencies::default();
+        let queued = vec![
+            (1, sim("a", State::Added)),
+            (2, battery_life()),
+            (3, sim("b", State::Added)),
+            (4, sim("c", State::Removed)),
+            (5, battery_life()),
+        ];
+
+        assert_eq!(compact(&queued, 5, &freqs), (Vec::new(), Vec::new()));
+
+        // Summaries first, then the oldest inventory, which is rolled back
+        let (ids, rollback) = compact(&queued, 2, &freqs);
+        assert_eq!(ids, vec![2, 5, 1]);
+        assert_eq!(rollback, vec![sim("a", State::Added)]);
+    }
+
+    #[test]
+    fn compact_cancelled() {
+        let freqs = Frequencies::default();
+        let queued = vec![
+            (1, sim("a", State::Added)),
+            (2, sim("b", State::Added)),
+            (3, sim("a", State::Removed)),
+        ];
+
+        // Added then removed again, so nothing to roll back
+        let (mut ids, rollback) = compact(&queued, 2, &freqs);
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(rollback, Vec::new());
+    }
+
+    #[test]
+    fn rollback_queues_again() {
+        let current = vec![sim("a", State::Added), sim("b", State::Added)];
+        let mut state = current.clone();
+        rollback(&mut state, &sim("a", State::Added));
+        rollback(&mut state, &sim("c", State::Removed));
+
+        let mut events = current;
+        diff(&mut events, &state);
+        assert_eq!(
+            events,
+            vec![sim("a", State::Added), sim("c", State::Removed)]
+        );
+    }
+}
@@ -78,9 +78,9 @@ fn default_frequency(type_: TelemetryEventType) -> SamplingFrequency {
         TelemetryEventType::HwNvmeSmartLog => Daily,
         TelemetryEventType::HwNvmeStorageLogical => Daily,
         TelemetryEventType::HwNvmeStoragePhysical => Daily,
-        TelemetryEventType::HwPeripheralAudioPort => Daily,
+        TelemetryEventType::HwPeripheralAudioPort => OnChange,
         TelemetryEventType::HwPeripheralUsb => OnChange,
-        TelemetryEventType::HwPeripheralSimCard => Daily,
+        TelemetryEventType::HwPeripheralSimCard => OnChange,
         TelemetryEventType::HwProcessor => Daily,
         TelemetryEventType::HwSystem => Daily,
         TelemetryEventType::HwThermalSummary => Daily,
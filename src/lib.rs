@@ -4,15 +4,14 @@
 
 use nix::sys::utsname::uname;
 use os_release::OS_RELEASE;
-use plain::Plain;
 use std::{
     collections::HashMap,
     convert::TryInto,
     ffi::OsStr,
     fs, io,
-    path::PathBuf,
-    process::{self, Command},
-    str::FromStr,
+    path::{Path, PathBuf},
+    process,
+    str::{self, FromStr},
 };
 
 mod api;
@@ -22,30 +21,101 @@ mod config;
 mod db;
 pub mod event;
 mod frequency;
+mod trigger;
 mod util;
 
 use config::SamplingFrequency;
 use event::{read_file, unknown, State, TelemetryEvent, TelemetryEventType};
 use frequency::Frequencies;
+use trigger::Trigger;
 use util::{
-    dmi::{dmi, CacheInfo21},
+    dmi::{cache_installed_size, dmi, CacheInfo21},
     drm::DrmDevice,
 };
 
+// Batteries powering the system, rather than peripherals like mice
+fn is_system_battery(path: &Path) -> bool {
+    let type_ = fs::read(path.join("type")).ok();
+    let scope = fs::read(path.join("scope")).ok();
+    type_.as_deref() == Some(b"Battery\n") && scope.as_deref() != Some(b"Device\n")
+}
+
 fn battery() -> Option<PathBuf> {
     for entry in fs::read_dir("/sys/class/power_supply").ok()? {
-        let entry = entry.ok()?;
-        let path = entry.path();
-        if let Ok(type_) = fs::read(path.join("type")) {
-            let scope = fs::read(path.join("scope")).ok();
-            if type_ == b"Battery\n" && scope.as_deref() != Some(b"Device\n") {
-                return Some(path);
-            }
+        let path = entry.ok()?.path();
+        if is_system_battery(&path) {
+            return Some(path);
         }
     }
     None
 }
 
+fn udev_property(device: &udev::Device, name: &str) -> Option<String> {
+    Some(device.property_value(name)?.to_str()?.to_string())
+}
+
+// Undo escaping of values like `ID_PART_ENTRY_NAME`, which uses `\x20` for space
+fn unescape_udev(value: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'\\' && tail.first() == Some(&b'x') {
+            if let Some(hex) = tail.get(1..3) {
+                if let Some(x) = str::from_utf8(hex)
+                    .ok()
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                {
+                    bytes.push(x);
+                    rest = &tail[3..];
+                    continue;
+                }
+            }
+        }
+        bytes.push(byte);
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+const ESP_PART_TYPE: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+
+// Flags like `parted` shows
+fn partition_flags(device: &udev::Device) -> Vec<String> {
+    let mut flags = Vec::new();
+    let scheme = udev_property(device, "ID_PART_ENTRY_SCHEME");
+    let type_ = udev_property(device, "ID_PART_ENTRY_TYPE");
+    let entry_flags = udev_property(device, "ID_PART_ENTRY_FLAGS")
+        .and_then(|x| u64::from_str_radix(x.trim_start_matches("0x"), 16).ok())
+        .unwrap_or(0);
+
+    if type_.as_deref() == Some(ESP_PART_TYPE) {
+        flags.push("boot");
+        flags.push("esp");
+    }
+    match scheme.as_deref() {
+        Some("dos") => {
+            if entry_flags & 0x80 != 0 {
+                flags.push("boot");
+            }
+        }
+        Some("gpt") => {
+            for (bit, name) in [
+                (0, "required"),
+                (2, "legacy_boot"),
+                (60, "read_only"),
+                (62, "hidden"),
+                (63, "no_automount"),
+            ] {
+                if entry_flags & (1 << bit) != 0 {
+                    flags.push(name);
+                }
+            }
+        }
+        _ => {}
+    }
+    flags.into_iter().map(|x| x.to_string()).collect()
+}
+
 pub struct PeriodicEventDesc {
     cb: fn(&mut Vec<TelemetryEvent>),
 }
@@ -122,11 +192,12 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                 .into(),
             );
         }),
-        TelemetryEventType::HwBattery => EventDesc::new(|events| {
-            let path = match battery() {
-                Some(path) => path,
-                None => return,
-            };
+        TelemetryEventType::HwBattery => EventDesc::new_udev("power_supply", |events, device| {
+            // AC adapters and peripheral batteries are also `power_supply`
+            let path = device.syspath();
+            if !is_system_battery(path) {
+                return;
+            }
 
             events.push(
                 event::Battery {
@@ -166,12 +237,8 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                         Some(format!("{}-{}-{}", year, month, day))
                     })();
                     let ec_version = format!("{}.{}", bios.ec_major, bios.ec_minor);
-                    let smbios_version = (|| {
-                        let entry_point =
-                            fs::read("/sys/firmware/dmi/tables/smbios_entry_point").ok()?;
-                        let smbios = dmi::Smbios3::from_bytes(&entry_point).ok()?;
-                        Some(format!("{}.{}", smbios.major_version, smbios.minor_version))
-                    })();
+                    let smbios_version = util::dmi::smbios_version()
+                        .map(|(major, minor)| format!("{}.{}", major, minor));
                     let mut rom_size = (bios.rom_size as u16 + 1) / 16;
                     if bios.rom_size == 0xff {
                         let unit = bios.extended_rom_size >> 14;
@@ -189,7 +256,7 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                                 .unwrap_or_else(unknown),
                             bios_vendor: i.get_str(bios.vendor).cloned(),
                             bios_version: i.get_str(bios.version).cloned(),
-                            capabilities: None, // XXX
+                            capabilities: Some(bios.capabilities()),
                             embedded_controller_version: Some(ec_version),
                             rom_size: Some(rom_size.into()),
                             smbios_version,
@@ -202,12 +269,33 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
             }
         }),
         TelemetryEventType::HwSystem => EventDesc::new(|events| {
+            // Like `lshw`
+            let mut capabilities = Vec::new();
+            if let Some((major, minor)) = util::dmi::smbios_version() {
+                capabilities.push(format!("smbios-{}.{}", major, minor));
+                capabilities.push(format!("dmi-{}.{}", major, minor));
+            }
+            if read_file::<_, String>("/sys/devices/system/cpu/present").map_or(false, |x| x != "0")
+            {
+                capabilities.push("smp".to_string());
+            }
+            if Path::new("/proc/sys/abi/vsyscall32").exists() {
+                capabilities.push("vsyscall32".to_string());
+            }
+
+            // HP stores it in an OEM string, like `FBYTE#3K3Q6b7K7M7WaBaNapaqasawbhbzcbdUdXdpdq.mD`
+            let feature_byte = dmi().iter().find_map(|i| {
+                let oem_strings = i.get::<util::dmi::OemStrings11>()?;
+                (1..=oem_strings.count)
+                    .find_map(|n| Some(i.get_str(n)?.strip_prefix("FBYTE#")?.to_string()))
+            });
+
             events.push(
                 event::System {
-                    capabilities: None, // XXX
+                    capabilities: Some(capabilities),
                     chassis: read_file("/sys/class/dmi/id/chassis_type"),
                     family: read_file("/sys/class/dmi/id/product_family"),
-                    feature_byte: None, // XXX
+                    feature_byte,
                     manufacturer: read_file("/sys/class/dmi/id/sys_vendor"),
                     model: read_file("/sys/class/dmi/id/product_name"),
                     serialnumber: read_file("/sys/class/dmi/id/product_serial")
@@ -220,9 +308,24 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
         }),
         TelemetryEventType::SwOperatingSystem => EventDesc::new(|events| {
             let os_release = OS_RELEASE.as_ref().ok();
+
+            // The EFI system partition if mounted, otherwise where the
+            // kernel was likely loaded from
+            let mounts = read_file::<_, String>("/proc/self/mounts").unwrap_or_default();
+            let mount_source = |mount_point: &str| {
+                mounts.lines().find_map(|line| {
+                    let mut cols = line.split(' ');
+                    let source = cols.next()?;
+                    (cols.next()? == mount_point).then(|| source.to_string())
+                })
+            };
+            let boot_device = ["/boot/efi", "/efi", "/boot", "/"]
+                .iter()
+                .find_map(|x| mount_source(x));
+
             events.push(
                 event::OperatingSystem {
-                    boot_device: None, // XXX
+                    boot_device,
                     codename: os_release.as_ref().map(|x| x.version_codename.to_owned()),
                     name: os_release.as_ref().map(|x| x.name.to_owned()),
                     version: os_release.map(|x| x.version.clone()),
@@ -239,27 +342,18 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                     let _instances = cols.next();
                     let _deps = cols.next();
                     let _state = cols.next();
-                    let modinfo = |field| {
-                        let res = Command::new("/usr/sbin/modinfo")
-                            .args(["-F", field, module_name])
-                            .output()
-                            .ok()?;
-                        let mut s = String::from_utf8(res.stdout).ok()?;
-                        s.truncate(s.trim_end().len());
-                        if !res.status.success() || s.is_empty() {
-                            return None;
-                        }
-                        Some(s)
-                    };
+                    let modinfo = util::module::modinfo(module_name).unwrap_or_default();
                     events.push(
                         event::Driver {
-                            author: modinfo("author"),
-                            description: modinfo("description"),
-                            driver_version: modinfo("version"),
-                            link_time: None, // XXX
+                            author: modinfo.get("author"),
+                            description: modinfo.get("description"),
+                            driver_version: modinfo.get("version"),
+                            // XXX Not recorded in the module, and its
+                            // modification time is when it was installed
+                            link_time: None,
                             module_name: module_name.to_string(),
-                            module_path: modinfo("filename").unwrap_or_else(unknown),
-                            module_type: String::new(), // XXX
+                            module_path: modinfo.get("filename").unwrap_or_else(unknown),
+                            module_type: "loadable".to_string(),
                             size,
                             state: State::Added,
                         }
@@ -267,10 +361,41 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                     );
                 }
             }
+
+            for (module_name, modinfo) in util::module::builtin_modules(uname().release()) {
+                events.push(
+                    event::Driver {
+                        author: modinfo.get("author"),
+                        description: modinfo.get("description"),
+                        driver_version: modinfo.get("version"),
+                        // XXX Likewise not recorded
+                        link_time: None,
+                        module_name,
+                        // Like `modinfo`
+                        module_path: "(builtin)".to_string(),
+                        module_type: "builtin".to_string(),
+                        size: None,
+                        state: State::Added,
+                    }
+                    .into(),
+                );
+            }
         }),
         TelemetryEventType::HwNvmeStoragePhysical => {
             EventDesc::new_udev("nvme", |events, device| {
                 let path = device.syspath();
+
+                // In GB, like `nvme list`
+                let total_capacity = device.devnode().and_then(|devnode| {
+                    let tnvmcap = util::nvme::controller_id(devnode).map_or(0, |x| x.tnvmcap);
+                    let bytes = if tnvmcap != 0 {
+                        tnvmcap.try_into().ok()?
+                    } else {
+                        util::nvme::namespace_id(devnode)?.size()?
+                    };
+                    Some((bytes / 1_000_000_000).to_string())
+                });
+
                 events.push(
                     event::NvmestoragePhysical {
                         bus_info: read_file(path.join("address")),
@@ -279,7 +404,7 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                         serial_number: read_file(path.join("serial")).unwrap_or_else(unknown),
                         state: State::Added,
                         sub_system_id: read_file(path.join("device/subsystem_vendor")),
-                        total_capacity: None, // XXX
+                        total_capacity,
                         vendor_id: read_file(path.join("device/vendor")),
                     }
                     .into(),
@@ -311,8 +436,12 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                                     .property_value("ID_FS_TYPE")
                                     .and_then(|x| x.to_str())
                                     .map(|x| x.to_string()),
-                                flags: Vec::new(),   // XXX
-                                name: String::new(), // XXX
+                                flags: partition_flags(&child),
+                                name: udev_property(&child, "ID_PART_ENTRY_NAME")
+                                    .map(|x| unescape_udev(&x))
+                                    .unwrap_or_else(|| {
+                                        child.sysname().to_string_lossy().into_owned()
+                                    }),
                                 number,
                                 size: read_file(path.join("size")).unwrap_or(0),
                             })
@@ -328,7 +457,9 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
 
                         events.push(
                             event::NvmestorageLogical {
-                                node_id: String::new(), // XXX
+                                node_id: device
+                                    .devnode()
+                                    .map_or_else(unknown, |x| x.display().to_string()),
                                 partitions: partitions(device).ok(),
                                 serial_number: read_file(path.join("device/serial"))
                                     .unwrap_or_else(unknown),
@@ -349,6 +480,10 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
 
             let smart_log = util::nvme::smart_log(devnode);
             let controller_id = util::nvme::controller_id(devnode);
+            // In GB, rounded up
+            let used_capacity = util::nvme::namespace_id(devnode)
+                .and_then(|x| x.used())
+                .map_or(-1, |x| ((x + 999_999_999) / 1_000_000_000) as i64);
             if let (Some(smart_log), Some(controller_id)) = (smart_log, controller_id) {
                 events.push(
                     event::NvmesmartLog {
@@ -385,7 +520,7 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                         ],
                         timestamp: event::date_time(),
                         unsafe_shutdowns: smart_log.unsafe_shutdowns.try_into().unwrap_or(-1),
-                        used_capacity: -1, // XXX
+                        used_capacity,
                         warning_temp_threshold: controller_id.wctemp,
                         warning_temp_time: smart_log.warning_temp_time,
                     }
@@ -418,6 +553,46 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                 .into(),
             )
         }),
+        TelemetryEventType::HwPeripheralSimCard => EventDesc::new(|events| {
+            // Only reported while a SIM is inserted. There is no uevent when a
+            // SIM changes, so this isn't a udev type; the daemon watches
+            // ModemManager instead.
+            for sim_id in util::modem::sims() {
+                events.push(
+                    event::PeripheralSIMCard {
+                        sim_id: Some(sim_id),
+                        state: State::Added,
+                    }
+                    .into(),
+                );
+            }
+        }),
+        TelemetryEventType::HwPeripheralAudioPort => {
+            EventDesc::new_udev("input", |events, device| {
+                if !util::input::is_jack(device) {
+                    return;
+                }
+
+                // Only reported while something is plugged in
+                let inserted = device
+                    .devnode()
+                    .and_then(|x| fs::File::open(x).ok())
+                    .and_then(|x| util::input::jack_inserted(&x));
+                if inserted == Some(true) {
+                    let port = device
+                        .parent()
+                        .and_then(|x| Some(x.attribute_value("name")?.to_str()?.to_string()))
+                        .unwrap_or_else(|| device.sysname().to_string_lossy().into_owned());
+                    events.push(
+                        event::PeripheralAudioPort {
+                            port: Some(port),
+                            state: State::Added,
+                        }
+                        .into(),
+                    );
+                }
+            })
+        }
         TelemetryEventType::HwMemoryPhysical => EventDesc::new(|events| {
             for i in dmi() {
                 if let Some(info) = i.get::<dmi::MemoryDevice>() {
@@ -468,6 +643,13 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                         0x1B => "LPDDR",
                         0x1C => "LPDDR2",
                         0x1D => "LPDDR3",
+                        0x1E => "LPDDR4",
+                        0x1F => "Logical non-volatile device",
+                        0x20 => "HBM",
+                        0x21 => "HBM2",
+                        0x22 => "DDR5",
+                        0x23 => "LPDDR5",
+                        0x24 => "HBM3",
                         _ => "Unknown",
                     }
                     .to_string();
@@ -487,8 +669,8 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                                 .get_str(info.serial_number)
                                 .cloned()
                                 .unwrap_or_else(unknown),
-                            size: Some(info.size.into()),
-                            speed: Some(info.speed.into()),
+                            size: util::dmi::memory_size(&i, info.size),
+                            speed: util::dmi::memory_speed(&i, info.speed),
                             state: State::Added,
                             type_: Some(type_),
                         }
@@ -498,7 +680,40 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
             }
         }),
         TelemetryEventType::HwProcessor => EventDesc::new(|events| {
+            // Like `lshw`, from the first processor in `/proc/cpuinfo`
+            let capabilities = read_file::<_, String>("/proc/cpuinfo").map(|cpuinfo| {
+                let field = |name: &str| {
+                    cpuinfo
+                        .lines()
+                        .take_while(|line| !line.is_empty())
+                        .find_map(|line| {
+                            let (key, value) = line.split_once(':')?;
+                            (key.trim() == name).then(|| value.trim())
+                        })
+                };
+                let flags = field("flags")
+                    .unwrap_or("")
+                    .split_whitespace()
+                    .collect::<Vec<_>>();
+                let mut capabilities = Vec::new();
+                if flags.contains(&"lm") {
+                    capabilities.push("x86-64".to_string());
+                }
+                for flag in flags {
+                    capabilities.push(flag.to_string());
+                    if flag == "fpu" {
+                        for name in ["fpu_exception", "wp"] {
+                            if field(name) == Some("yes") {
+                                capabilities.push(name.to_string());
+                            }
+                        }
+                    }
+                }
+                capabilities
+            });
+
             let dmi = dmi();
+            let mut cpu_number = 0;
             for i in &dmi {
                 if let Some(processor) = i.get::<dmi::ProcessorInfo>() {
                     let mut cache_infos = Vec::new();
@@ -512,7 +727,7 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                         }
                         if let Some(cache) = dmi.iter().find(|x| x.header.handle == i) {
                             if let Some(cache_info) = cache.get::<CacheInfo21>() {
-                                cache_infos.push(cache_info);
+                                cache_infos.push((cache_info, cache_installed_size(cache)));
                                 // Seems to handle non-unified L1
                                 if cache_info.socket != 0 {
                                     for j in &dmi {
@@ -523,7 +738,10 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                                             if cache.get_str(other_cache_info.socket)
                                                 == j.get_str(cache_info.socket)
                                             {
-                                                cache_infos.push(other_cache_info)
+                                                cache_infos.push((
+                                                    other_cache_info,
+                                                    cache_installed_size(j),
+                                                ))
                                             }
                                         }
                                     }
@@ -534,7 +752,7 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
 
                     let caches = cache_infos
                         .iter()
-                        .map(|cache_info| {
+                        .map(|(cache_info, size)| {
                             let level = (cache_info.configuration & 0b111) + 1;
                             let type_ = match cache_info.system_cache_type {
                                 0x01 => "Other",
@@ -546,36 +764,67 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                             };
                             event::ProcessorCache {
                                 name: format!("L{} {}-Cache", level, type_),
-                                size: cache_info.installed_size.into(), // TODO support larger size w/ newer smbios
+                                size: size.unwrap_or(0),
                             }
                         })
                         .collect();
 
+                    let (cores_count, cores_enabled, thread_count) =
+                        match util::dmi::ProcessorInfo30::get(i) {
+                            Some(info) => info.counts(),
+                            None => (
+                                processor.core_count.into(),
+                                processor.core_enabled.into(),
+                                processor.thread_count.into(),
+                            ),
+                        };
+
+                    // EDX and EAX of CPUID leaf 1, like Windows
                     let processor_id = processor.processor_id;
+
+                    // From EAX, like `dmidecode`
+                    let eax = processor_id as u32;
+                    let base_family = (eax >> 8) & 0xf;
+                    let family = if base_family == 0xf {
+                        base_family + ((eax >> 20) & 0xff)
+                    } else {
+                        base_family
+                    };
+                    let model = if base_family == 0x6 || base_family == 0xf {
+                        ((eax >> 12) & 0xf0) | ((eax >> 4) & 0xf)
+                    } else {
+                        (eax >> 4) & 0xf
+                    };
+                    let signature =
+                        format!("Family {}, Model {}, Stepping {}", family, model, eax & 0xf);
+
                     events.push(
                         event::Processor {
                             caches: Some(caches),
-                            capabilities: None, // XXX
-                            cores_count: Some(processor.core_count.into()),
-                            cores_enabled: Some(processor.core_enabled.into()),
-                            device_id: String::new(), // XXX
+                            capabilities: capabilities.clone(),
+                            cores_count: Some(cores_count),
+                            cores_enabled: Some(cores_enabled),
+                            device_id: format!("CPU{}", cpu_number),
                             manufacturer: i.get_str(processor.processor_manufacturer).cloned(),
                             max_clock_speed: Some(i64::from(processor.max_speed)),
                             name: i.get_str(processor.processor_version).cloned(),
-                            processor_id: format!("{:X}", processor_id), // XXX: correct?
-                            signature: None, // XXX where does dmidecocode get this?
+                            processor_id: format!("{:X}", processor_id),
+                            signature: Some(signature),
                             socket: i.get_str(processor.socket_designation).cloned(),
                             state: State::Added,
-                            thread_count: Some(processor.thread_count.into()),
+                            thread_count: Some(thread_count),
                             voltage: Some(f64::from(processor.voltage) / 10.),
                         }
                         .into(),
                     );
+                    cpu_number += 1;
                 }
             }
         }),
         TelemetryEventType::HwDisplay => EventDesc::new_udev("drm", |events, device| {
-            // TODO if possible, would base on connector device; but can't seem to map to DRM
+            // Connector devices can't be mapped to DRM connectors, so the
+            // daemon treats their uevents as changes of the card. Each monitor
+            // is still identified by its connector and EDID.
 
             if !device.sysname().to_str().unwrap_or("").starts_with("card") {
                 return;
@@ -596,12 +845,16 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                     let pixel_size = drm_device
                         .connector_preferred_mode(&connector)
                         .map(|mode| mode.size());
+                    let edid = drm_device.connector_edid(&connector);
                     events.push(
                         event::Display {
                             bus_id: bus_id.clone(),
                             port,
                             pixel_width: pixel_size.map(|x| x.0 as i64),
                             pixel_height: pixel_size.map(|x| x.1 as i64),
+                            manufacturer: edid.map(|x| x.manufacturer()),
+                            product_code: edid.map(|x| x.product_code() as i64),
+                            serial_number: edid.and_then(|x| x.serial_number()).map(i64::from),
                             state: State::Added,
                         }
                         .into(),
@@ -609,6 +862,154 @@ pub fn event(type_: TelemetryEventType) -> Option<EventDesc> {
                 }
             }
         }),
+        TelemetryEventType::HwGraphicsCard => EventDesc::new_udev("drm", |events, device| {
+            let name = device.sysname().to_str().unwrap_or("");
+            // Skip connectors like `card0-eDP-1`, and render nodes
+            if !name.starts_with("card") || name.contains('-') {
+                return;
+            }
+
+            let pci_device = match device.parent_with_subsystem("pci") {
+                Ok(Some(pci_device)) => pci_device,
+                _ => {
+                    return;
+                }
+            };
+            let bus_id = match device
+                .devnode()
+                .and_then(DrmDevice::open)
+                .and_then(|x| x.bus_id())
+            {
+                Some(bus_id) => bus_id,
+                None => {
+                    return;
+                }
+            };
+            let path = pci_device.syspath();
+
+            // There's no generic way to ask the kernel if a GPU is integrated.
+            // Intel dGPUs aren't on the root bus, and `amdgpu` only exposes a
+            // VRAM vendor for dedicated VRAM.
+            let vendor = pci_device
+                .attribute_value("vendor")
+                .and_then(OsStr::to_str)
+                .unwrap_or("");
+            let integrated = match vendor {
+                util::pcie::PCI_VENDOR_ID_INTEL => pci_device
+                    .sysname()
+                    .to_str()
+                    .unwrap_or("")
+                    .starts_with("0000:00:"),
+                util::pcie::PCI_VENDOR_ID_AMD => !path.join("mem_info_vram_vendor").exists(),
+                _ => false,
+            };
+            let type_ = if integrated { "Integrated" } else { "Discrete" };
+
+            let (chip_name, model) = match util::pcie::pci_model_name(&pci_device) {
+                Some((chip_name, model)) => (chip_name, Some(model)),
+                None => (None, None),
+            };
+
+            events.push(
+                event::GraphicsCard {
+                    // `pci:0000:04:00.0` -> `pci@0000:04:00.0`, like `lshw`
+                    bus_info: bus_id.replacen(':', "@", 1),
+                    chip_name,
+                    cores_count: None,
+                    manufacturer: util::pcie::pci_vendor_name(&pci_device),
+                    memory_size: read_file(path.join("mem_info_vram_total"))
+                        .map(|x: u64| (x / 1024 / 1024) as f64),
+                    model,
+                    state: State::Added,
+                    type_: type_.to_string(),
+                }
+                .into(),
+            );
+        }),
+        TelemetryEventType::HwNetworkCard => EventDesc::new_udev("net", |events, device| {
+            let path = device.syspath();
+
+            let mut capabilities = Vec::new();
+            let interface_type = match device.devtype().and_then(OsStr::to_str) {
+                Some("wlan") => {
+                    capabilities.push("wireless");
+                    "Wireless80211"
+                }
+                Some("wwan") => "Wwan",
+                // ARPHRD_ETHER
+                _ if read_file(path.join("type")) == Some(1) => {
+                    capabilities.push("ethernet");
+                    "Ethernet"
+                }
+                _ => "Unknown",
+            };
+
+            let mut card = event::NetworkCard {
+                bus_info: None,
+                capabilities: None,
+                clock_speed: None,
+                description: None,
+                interface_type: Some(interface_type.to_string()),
+                product_id: None,
+                serial_number: None,
+                state: State::Added,
+                vendor_id: None,
+                width: None,
+            };
+
+            // Check USB first, since USB controller may be a PCI device
+            if let Ok(Some(usb_device)) = device.parent_with_subsystem_devtype("usb", "usb_device")
+            {
+                let usb_path = usb_device.syspath();
+                card.bus_info = usb_device.sysname().to_str().map(|x| format!("usb@{}", x));
+                card.product_id = read_file(usb_path.join("idProduct"));
+                card.vendor_id = read_file(usb_path.join("idVendor"));
+                capabilities.push("usb");
+            } else if let Ok(Some(pci_device)) = device.parent_with_subsystem("pci") {
+                let pci_path = pci_device.syspath();
+                card.bus_info = pci_device.sysname().to_str().map(|x| format!("pci@{}", x));
+                card.clock_speed = read_file::<_, String>(pci_path.join("current_link_speed"))
+                    .and_then(|x| util::pcie::pcie_link_speed(&x));
+                card.product_id = read_file(pci_path.join("device"));
+                card.serial_number = util::pcie::pcie_dsn(pci_path.join("config"));
+                card.vendor_id = read_file(pci_path.join("vendor"));
+                card.width = read_file(pci_path.join("current_link_width"));
+                capabilities.extend(util::pcie::pci_capabilities(pci_path.join("config")));
+            } else {
+                // Virtual interface
+                return;
+            }
+            capabilities.push("physical");
+
+            // Use MAC address if there's no DSN, like `lshw`. The permanent
+            // one, since the current one may be random or set by userspace.
+            if card.serial_number.is_none() {
+                card.serial_number = device
+                    .sysname()
+                    .to_str()
+                    .and_then(util::ethtool::permanent_address);
+            }
+            card.description = match interface_type {
+                "Wireless80211" => Some("Wireless interface".to_string()),
+                "Ethernet" => Some("Ethernet interface".to_string()),
+                _ => None,
+            };
+            card.capabilities = Some(capabilities.iter().map(|x| x.to_string()).collect());
+
+            events.push(card.into());
+        }),
+        TelemetryEventType::HwTpm => EventDesc::new(|events| {
+            for tpm in util::tpm::tpms("/sys/class/tpm", "/dev") {
+                events.push(tpm.into());
+            }
+        }),
+        TelemetryEventType::SwBootPerformance => EventDesc::new(|events| {
+            // Keyed by boot ID, so it's queued once per boot, when it differs
+            // from the stored state. Nothing until the boot finishes.
+            if let Some(boot_performance) = util::boot::boot_performance() {
+                events.push(boot_performance.into());
+            }
+        }),
         _ => return None,
     })
 }
@@ -646,14 +1047,67 @@ pub fn events(freqs: &Frequencies, freq: SamplingFrequency) -> Vec<event::Teleme
     events_inner(event::TelemetryEventType::iter().filter(|i| freqs.get(*i) == freq))
 }
 
+// Types collected for `trigger`
+fn trigger_types(freqs: &Frequencies, trigger: Trigger) -> Vec<TelemetryEventType> {
+    event::TelemetryEventType::iter()
+        .filter(|i| {
+            freqs.get(*i) == SamplingFrequency::OnTrigger && i.triggers().contains(&trigger)
+        })
+        .collect()
+}
+
+pub fn trigger_events(freqs: &Frequencies, trigger: Trigger) -> Vec<event::TelemetryEvent> {
+    events_inner(trigger_types(freqs, trigger).into_iter())
+}
+
+/// Like `update_events_and_queue`, but only diffs and replaces state for the
+/// `on_trigger` types `trigger` applies to
+pub fn update_trigger_events_and_queue(
+    db: &db::DB,
+    freqs: &Frequencies,
+    trigger: Trigger,
+) -> rusqlite::Result<()> {
+    let types = trigger_types(freqs, trigger);
+    let new = trigger_events(freqs, trigger);
+
+    // See `update_events_and_queue`
+    let tx = db.transaction()?;
+    let mut old = Vec::new();
+    for type_ in &types {
+        old.extend(db.get_state(db::State::Type(*type_))?);
+    }
+
+    let mut diff = new.clone();
+    event::diff(&mut diff, &old);
+
+    let mut insert_statement = db.prepare_queue_insert()?;
+    for event in diff {
+        insert_statement.execute(&event)?;
+    }
+    for type_ in types {
+        let events = new
+            .iter()
+            .filter(|x| x.type_() == type_)
+            .cloned()
+            .collect::<Vec<_>>();
+        db.replace_state(db::State::Type(type_), &events)?;
+    }
+
+    tx.commit()
+}
+
 pub fn update_events_and_queue(
     db: &db::DB,
     freqs: &Frequencies,
     freq: SamplingFrequency,
 ) -> rusqlite::Result<()> {
-    let old = db.get_state(db::State::Frequency(freq))?;
-
     let new = events(&freqs, freq);
+
+    // So the state can't change between the diff and replacing it, like by
+    // `limit_queued` rolling back dropped events. After collecting, so the
+    // write lock isn't held while collecting.
+    let tx = db.transaction()?;
+    let old = db.get_state(db::State::Frequency(freq))?;
     let mut diff = new.clone();
     event::diff(&mut diff, &old);
 
@@ -663,7 +1117,7 @@ pub fn update_events_and_queue(
     }
     db.replace_state(db::State::Frequency(freq), &new)?;
 
-    Ok(())
+    tx.commit()
 }
 
 pub fn exit_if_not_opted_in(db: &db::DB) {
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::event::TelemetryEventType;
+
+/// Something that happened to the system, which causes the daemon to collect
+/// types with the `on_trigger` frequency that have it in
+/// `TelemetryEventType::triggers`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// Boot finished, the first time the daemon starts in a boot
+    Boot,
+    /// Resume from suspend
+    Resume,
+    /// AC adapter plugged in or removed
+    AcChange,
+    /// Thunderbolt dock connected
+    Dock,
+    /// Oops, panic, or warning logged by the kernel
+    KernelWarning,
+}
+
+impl TelemetryEventType {
+    /// Triggers that can change what this type reports. Types that are
+    /// summaries of samples, or are already reported as they occur, have none.
+    pub fn triggers(self) -> &'static [Trigger] {
+        use Trigger::*;
+
+        match self {
+            Self::HwBaseBoard => &[Boot],
+            Self::HwBattery => &[Boot, Resume, AcChange],
+            Self::HwBatteryLife => &[],
+            Self::HwCoolingFanCyclesSummary => &[],
+            Self::HwDisplay => &[Boot, Resume, Dock],
+            Self::HwGraphicsCard => &[Boot, Dock],
+            Self::HwMemoryPhysical => &[Boot],
+            Self::HwNetworkCard => &[Boot, Resume, Dock],
+            Self::HwNvmeSmartLog => &[Boot, Resume, KernelWarning],
+            Self::HwNvmeStorageLogical => &[Boot],
+            Self::HwNvmeStoragePhysical => &[Boot, KernelWarning],
+            Self::HwPeripheralAudioPort => &[Resume, Dock],
+            Self::HwPeripheralUsb => &[Resume, Dock],
+            Self::HwPeripheralSimCard => &[Boot, Resume],
+            Self::HwProcessor => &[Boot],
+            Self::HwSystem => &[Boot],
+            Self::HwThermalSummary => &[],
+            Self::HwTpm => &[Boot],
+            Self::SwBootPerformance => &[Boot],
+            Self::SwDriver => &[Boot, KernelWarning],
+            Self::SwFirmware => &[Boot],
+            Self::SwLinuxDriverCrash => &[],
+            Self::SwLinuxKernel => &[Boot, KernelWarning],
+            Self::SwOperatingSystem => &[Boot],
+        }
+    }
+}
@@ -4,16 +4,38 @@
 
 use std::{fs, io, os::unix::fs::PermissionsExt, process};
 
+pub mod boot;
+pub mod bus;
+pub mod crash;
 pub mod dmi;
 pub mod drm;
+pub mod ethtool;
+pub mod input;
 pub mod lock;
+pub mod logind;
+pub mod modem;
+pub mod module;
 pub mod nvme;
 pub mod pcie;
+pub mod profile;
 mod sensors;
 pub use sensors::*;
 pub mod systemd;
+#[cfg(test)]
+pub mod test;
+pub mod tpm;
 
-pub use hp_vendor_client::conf::{hp_vendor_conf, HpVendorConf};
+pub use hp_vendor_client::conf::{hp_vendor_conf, HpVendorConf, Limits, SamplingConf};
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases
+pub fn stable_hash(data: &[u8]) -> i64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash as i64
+}
 
 fn create_var_dir() -> io::Result<()> {
     fs::create_dir("/var/hp-vendor")?;
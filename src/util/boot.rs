@@ -0,0 +1,215 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    convert::TryInto,
+    fs, io,
+    os::unix::io::{AsRawFd, RawFd},
+    process::Command,
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::bus::BusMonitor;
+use crate::event::BootPerformance;
+
+const STARTUP_FINISHED_MATCH: &str = "type='signal',sender='org.freedesktop.systemd1',\
+    interface='org.freedesktop.systemd1.Manager',member='StartupFinished'";
+
+// Properties of `org.freedesktop.systemd1.Manager`, in microseconds. Like in
+// `systemd-analyze`, firmware and loader times count back from kernel start.
+const PROPERTIES: &[&str] = &[
+    "FirmwareTimestampMonotonic",
+    "LoaderTimestampMonotonic",
+    "InitRDTimestampMonotonic",
+    "UserspaceTimestampMonotonic",
+    "FinishTimestampMonotonic",
+    "KernelTimestamp",
+    "FinishTimestamp",
+];
+
+fn format_unix_time(time: i64) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(time)
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
+fn usec_to_msec(usec: u64) -> i64 {
+    (usec / 1000) as i64
+}
+
+// Boot time, from `/proc/stat`
+fn btime() -> Option<i64> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime ")?.trim().parse().ok())
+}
+
+/// Random ID the kernel generates each boot
+pub fn boot_id() -> Option<String> {
+    let id = fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    Some(id.trim().to_string())
+}
+
+// Parses a time span printed by systemd, like `1min 2.345s`, in milliseconds
+fn parse_timespan(s: &str) -> Option<i64> {
+    let mut msec = 0.;
+    for part in s.split_whitespace() {
+        let idx = part.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let (value, unit) = part.split_at(idx);
+        let value = value.parse::<f64>().ok()?;
+        let scale = match unit {
+            "us" | "µs" => 0.001,
+            "ms" => 1.,
+            "s" => 1000.,
+            "min" => 60_000.,
+            "h" => 3_600_000.,
+            _ => {
+                return None;
+            }
+        };
+        msec += value * scale;
+    }
+    Some(msec as i64)
+}
+
+fn manager_properties() -> Option<[u64; 7]> {
+    let output = Command::new("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.systemd1",
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+        ])
+        .args(PROPERTIES)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Prints a line like `t 1234` for each property
+    let values = String::from_utf8(output.stdout)
+        .ok()?
+        .lines()
+        .map(|line| line.strip_prefix("t ")?.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    values.try_into().ok()
+}
+
+fn from_manager() -> Option<BootPerformance> {
+    let [firmware, loader, initrd, userspace, finish, kernel_realtime, finish_realtime] =
+        manager_properties()?;
+
+    // Boot hasn't finished yet
+    if finish == 0 {
+        return None;
+    }
+
+    let mut details = Vec::new();
+    if firmware > 0 {
+        details.push(("firmware", firmware.saturating_sub(loader)));
+    }
+    if loader > 0 {
+        details.push(("loader", loader));
+    }
+    if initrd > 0 {
+        details.push(("kernel", initrd));
+        details.push(("initrd", userspace.saturating_sub(initrd)));
+    } else {
+        details.push(("kernel", userspace));
+    }
+    details.push(("userspace", finish.saturating_sub(userspace)));
+
+    // Realtime timestamps are unset in containers
+    let (last_boot_time, time) = if kernel_realtime != 0 && finish_realtime != 0 {
+        (
+            (kernel_realtime / 1_000_000) as i64,
+            (finish_realtime / 1_000_000) as i64,
+        )
+    } else {
+        let btime = btime()?;
+        (btime, btime + (finish / 1_000_000) as i64)
+    };
+
+    Some(BootPerformance {
+        boot_time_details: details
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), usec_to_msec(v)))
+            .collect(),
+        boot_time_total: usec_to_msec(firmware + finish),
+        boot_id: boot_id(),
+        last_boot_time: format_unix_time(last_boot_time)?,
+        time: format_unix_time(time)?,
+    })
+}
+
+// Parses output like `Startup finished in 7.055s (firmware) + 3.364s (loader)
+// + 2.364s (kernel) + 10.002s (userspace) = 22.787s`
+fn from_systemd_analyze() -> Option<BootPerformance> {
+    let output = Command::new("systemd-analyze").arg("time").output().ok()?;
+    // Fails if boot hasn't finished yet
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let line = stdout
+        .lines()
+        .next()?
+        .strip_prefix("Startup finished in ")?;
+    let (parts, total) = line.rsplit_once(" = ")?;
+    let boot_time_details = parts
+        .split(" + ")
+        .map(|part| {
+            let (time, name) = part.strip_suffix(')')?.rsplit_once(" (")?;
+            Some((name.to_string(), parse_timespan(time)?))
+        })
+        .collect::<Option<_>>()?;
+    let boot_time_total = parse_timespan(total)?;
+
+    let btime = btime()?;
+    Some(BootPerformance {
+        boot_id: boot_id(),
+        boot_time_details,
+        boot_time_total,
+        last_boot_time: format_unix_time(btime)?,
+        time: format_unix_time(btime + boot_time_total / 1000)?,
+    })
+}
+
+/// Timing of the current boot, or `None` until it finishes
+pub fn boot_performance() -> Option<BootPerformance> {
+    from_manager().or_else(from_systemd_analyze)
+}
+
+/// Whether systemd has finished starting up, or `None` if it can't be asked
+pub fn boot_finished() -> Option<bool> {
+    let [_, _, _, _, finish, _, _] = manager_properties()?;
+    Some(finish != 0)
+}
+
+/// Watches for systemd's `StartupFinished` signal, sent once per boot
+pub struct StartupMonitor(BusMonitor);
+
+impl StartupMonitor {
+    pub fn new() -> io::Result<Self> {
+        BusMonitor::new(STARTUP_FINISHED_MATCH).map(Self)
+    }
+
+    /// Whether startup finished since the last call
+    pub fn read(&mut self) -> bool {
+        !self.0.read().is_empty()
+    }
+
+    /// See `BusMonitor::exited`
+    pub fn exited(&self) -> Option<Duration> {
+        self.0.exited()
+    }
+}
+
+impl AsRawFd for StartupMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use serde_json::Value;
+use std::{
+    io::{self, ErrorKind, Read},
+    os::unix::io::{AsRawFd, RawFd},
+    process::{Child, ChildStdout, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Watches for D-Bus messages on the system bus matching a rule, with a
+/// `busctl monitor` child process
+pub struct BusMonitor {
+    child: Child,
+    stdout: ChildStdout,
+    buf: Vec<u8>,
+    started: Instant,
+    // How long `busctl` ran, once it exits
+    exited: Option<Duration>,
+}
+
+impl BusMonitor {
+    pub fn new(rule: &str) -> io::Result<Self> {
+        let mut child = Command::new("busctl")
+            .args(["monitor", "--system", "--json=short", "--match", rule])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        fcntl(stdout.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        Ok(Self {
+            child,
+            stdout,
+            buf: Vec::new(),
+            started: Instant::now(),
+            exited: None,
+        })
+    }
+
+    /// How long `busctl` ran, if it exited, like when the bus restarts.
+    /// Nothing more is received after.
+    pub fn exited(&self) -> Option<Duration> {
+        self.exited
+    }
+
+    /// Messages received since the last call, like
+    /// `{..., "payload": {"type": "b", "data": [true]}}`
+    pub fn read(&mut self) -> Vec<Value> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stdout.read(&mut chunk) {
+                Ok(0) => {
+                    self.exited = Some(self.started.elapsed());
+                    break;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    break;
+                }
+                Ok(len) => self.buf.extend_from_slice(&chunk[..len]),
+            }
+        }
+
+        // A message per line
+        let mut messages = Vec::new();
+        while let Some(idx) = self.buf.iter().position(|x| *x == b'\n') {
+            let line = self.buf.drain(..=idx).collect::<Vec<_>>();
+            if let Ok(message) = serde_json::from_slice(&line) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+}
+
+impl AsRawFd for BusMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
+    }
+}
+
+impl Drop for BusMonitor {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
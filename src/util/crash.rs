@@ -0,0 +1,486 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nix::time::{clock_gettime, ClockId};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+use crate::event::{date_time, read_file, unknown, LinuxDriverCrash};
+
+// Give up on a block without an end marker after this long
+const MAX_BLOCK_USEC: u64 = 10_000_000;
+
+#[derive(Debug)]
+struct Crash {
+    panic: &'static str,
+    timestamp: u64,
+    pid: Option<i64>,
+    command: Option<String>,
+    task: Option<String>,
+    release: Option<String>,
+}
+
+impl Crash {
+    fn new(panic: &'static str, timestamp: u64) -> Self {
+        Self {
+            panic,
+            timestamp,
+            pid: None,
+            command: None,
+            task: None,
+            release: None,
+        }
+    }
+
+    // Line like `CPU: 3 PID: 1234 Comm: insmod Tainted: G OE 5.15.0-43-generic #46-Ubuntu`
+    fn parse_cpu_line(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        let mut prev: Option<&str> = None;
+        while let Some(word) = words.next() {
+            match word {
+                "PID:" => self.pid = words.next().and_then(|x| x.parse().ok()),
+                "Comm:" => self.command = words.next().map(|x| x.to_string()),
+                // Release precedes the build number
+                _ if word.starts_with('#') => self.release = prev.map(|x| x.to_string()),
+                _ => {}
+            }
+            prev = Some(word);
+        }
+    }
+
+    // State of the system is only known for a crash in the running kernel
+    fn into_event(self, live: bool) -> LinuxDriverCrash {
+        // Saved logs may lack timestamps
+        let up_time = (self.timestamp != 0).then(|| (self.timestamp / 1_000_000) as i64);
+        if !live {
+            return LinuxDriverCrash {
+                command: self.command,
+                // Not known for the crashed system
+                cpu_cores_count: None,
+                crash_time: date_time(),
+                dump_file: None,
+                kernel: self.release.unwrap_or_else(unknown),
+                load_average: None,
+                panic: Some(self.panic.to_string()),
+                pid: self.pid,
+                process_state: None,
+                task: self.task,
+                tasks_count: None,
+                up_time,
+            };
+        }
+
+        let loadavg = fs::read_to_string("/proc/loadavg").unwrap_or_default();
+        let loadavg = loadavg.split_whitespace().collect::<Vec<_>>();
+        let load_average = loadavg.get(..3).map(|x| x.join(", "));
+        // Runnable and total scheduling entities, like `1/234`
+        let tasks_count = loadavg
+            .get(3)
+            .and_then(|x| x.split_once('/'))
+            .and_then(|(_, total)| total.parse().ok());
+
+        // Only meaningful if the task survived, like after a `WARNING`
+        let process_state = self.pid.and_then(|pid| {
+            let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+            status
+                .lines()
+                .find_map(|line| Some(line.strip_prefix("State:")?.trim().to_string()))
+        });
+
+        let cpu_cores_count = fs::read_to_string("/sys/devices/system/cpu/online")
+            .ok()
+            .and_then(|x| cpu_count(&x));
+
+        LinuxDriverCrash {
+            command: self.command,
+            cpu_cores_count,
+            crash_time: kmsg_time(self.timestamp).unwrap_or_else(date_time),
+            dump_file: None,
+            kernel: read_file("/proc/sys/kernel/osrelease").unwrap_or_else(unknown),
+            load_average,
+            panic: Some(self.panic.to_string()),
+            pid: self.pid,
+            process_state,
+            task: self.task,
+            tasks_count,
+            up_time,
+        }
+    }
+}
+
+// Number of CPUs in a list like `0-3,6`
+fn cpu_count(list: &str) -> Option<i64> {
+    let mut count = 0;
+    for range in list.trim().split(',') {
+        count += match range.split_once('-') {
+            Some((first, last)) => last.parse::<i64>().ok()? - first.parse::<i64>().ok()? + 1,
+            None => range.parse::<i64>().map(|_| 1).ok()?,
+        };
+    }
+    Some(count)
+}
+
+// Wall clock time of a kernel log timestamp, which is in microseconds of
+// `CLOCK_MONOTONIC`
+fn kmsg_time(timestamp: u64) -> Option<String> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?;
+    let now = now.tv_sec() as i64 * 1_000_000 + now.tv_nsec() as i64 / 1_000;
+    let time = OffsetDateTime::now_utc() - Duration::microseconds(now - timestamp as i64);
+    // Second precision, like `date_time`
+    OffsetDateTime::from_unix_timestamp(time.unix_timestamp())
+        .ok()?
+        .format(&Rfc3339)
+        .ok()
+}
+
+/// Recognizes oops, `BUG`, `WARNING`, and panic blocks in kernel log messages,
+/// which span multiple records.
+#[derive(Debug)]
+pub struct CrashParser {
+    crash: Option<Crash>,
+    live: bool,
+}
+
+impl Default for CrashParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrashParser {
+    /// Parser for messages from the running kernel
+    pub fn new() -> Self {
+        Self {
+            crash: None,
+            live: true,
+        }
+    }
+
+    /// Parser for messages saved from a previous boot
+    fn saved() -> Self {
+        Self {
+            crash: None,
+            live: false,
+        }
+    }
+
+    /// Ends any crash block, even without an end marker
+    pub fn finish(&mut self, events: &mut Vec<LinuxDriverCrash>) {
+        if let Some(crash) = self.crash.take() {
+            events.push(crash.into_event(self.live));
+        }
+    }
+
+    fn start(&mut self, panic: &'static str, timestamp: u64, events: &mut Vec<LinuxDriverCrash>) {
+        if let Some(crash) = &mut self.crash {
+            // Like the `Oops:` line following `BUG: kernel NULL pointer
+            // dereference`, or a panic caused by an oops
+            if crash.pid.is_none() || panic == "Panic" {
+                crash.panic = panic;
+                return;
+            }
+        }
+        self.finish(events);
+        self.crash = Some(Crash::new(panic, timestamp));
+    }
+
+    /// Handles a message from the kernel log, with its timestamp in
+    /// microseconds since boot, adding an event if it ends a crash.
+    pub fn push(&mut self, timestamp: u64, message: &str, events: &mut Vec<LinuxDriverCrash>) {
+        if let Some(crash) = &self.crash {
+            if timestamp.saturating_sub(crash.timestamp) > MAX_BLOCK_USEC {
+                self.finish(events);
+            }
+        }
+
+        if message.starts_with("Kernel panic - not syncing") {
+            // Nothing follows this, so finish immediately
+            self.start("Panic", timestamp, events);
+            self.finish(events);
+        } else if message.starts_with("Oops: ") {
+            self.start("Oops", timestamp, events);
+        } else if message.starts_with("BUG: ") || message.starts_with("kernel BUG at ") {
+            self.start("BUG", timestamp, events);
+        } else if let Some(rest) = message.strip_prefix("WARNING: ") {
+            self.start("WARNING", timestamp, events);
+            // Like `WARNING: CPU: 0 PID: 1 at drivers/foo.c:123 foo+0x12/0x34`
+            if let Some(crash) = &mut self.crash {
+                crash.parse_cpu_line(rest);
+            }
+        } else if let Some(crash) = &mut self.crash {
+            if message.starts_with("CPU: ") {
+                crash.parse_cpu_line(message);
+            } else if let Some(task) = message.strip_prefix("task: ") {
+                // Only printed by older kernels
+                crash.task = task.split_whitespace().next().map(|x| x.to_string());
+            } else if message.starts_with("---[ end ") {
+                self.finish(events);
+            }
+        }
+    }
+}
+
+// Line like `<4>[   12.345678] message`, as saved by pstore and kdump-tools
+fn parse_saved_line(line: &str) -> (u64, &str) {
+    let mut line = line.trim_end();
+    if line.starts_with('<') {
+        if let Some((_, rest)) = line.split_once('>') {
+            line = rest;
+        }
+    }
+    let mut timestamp = 0;
+    if let Some(rest) = line.strip_prefix('[') {
+        if let Some((time, rest)) = rest.split_once(']') {
+            if let Some((secs, usecs)) = time.trim().split_once('.') {
+                if let (Ok(secs), Ok(usecs)) = (secs.parse::<u64>(), usecs.parse::<u64>()) {
+                    timestamp = secs * 1_000_000 + usecs;
+                }
+            }
+            line = rest.strip_prefix(' ').unwrap_or(rest);
+        }
+    }
+    (timestamp, line)
+}
+
+fn mtime(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+/// A kernel log saved by pstore or kdump-tools, after a crash in a previous
+/// boot.
+#[derive(Debug)]
+pub struct CrashDump {
+    /// Log of the crash
+    pub path: PathBuf,
+    /// Hash of the log, which identifies it even after `systemd-pstore`
+    /// moves it out of pstore, or when a name is reused
+    pub hash: i64,
+    /// Modification time of the log
+    time: i64,
+    dump_file: Option<PathBuf>,
+}
+
+impl CrashDump {
+    pub fn event(&self) -> LinuxDriverCrash {
+        let log = fs::read_to_string(&self.path).unwrap_or_default();
+
+        let mut parser = CrashParser::saved();
+        let mut crashes = Vec::new();
+        for line in log.lines() {
+            let (timestamp, message) = parse_saved_line(line);
+            parser.push(timestamp, message, &mut crashes);
+        }
+        parser.finish(&mut crashes);
+
+        // The last crash is the one that brought the system down
+        let mut crash = crashes.pop().unwrap_or_else(|| {
+            // pstore header, like `Panic#1 Part1`
+            let panic = match log.lines().next().and_then(|x| x.split('#').next()) {
+                Some("Oops") => "Oops",
+                _ => "Panic",
+            };
+            Crash::new(panic, 0).into_event(false)
+        });
+        if crash.kernel == unknown() {
+            if let Some(release) = log.lines().find_map(|line| {
+                let (_, message) = parse_saved_line(line);
+                message
+                    .strip_prefix("Linux version ")?
+                    .split_whitespace()
+                    .next()
+            }) {
+                crash.kernel = release.to_string();
+            }
+        }
+        if let Some(crash_time) = OffsetDateTime::from_unix_timestamp(self.time)
+            .ok()
+            .and_then(|x| x.format(&Rfc3339).ok())
+        {
+            crash.crash_time = crash_time;
+        }
+        let dump_file = self.dump_file.as_ref().unwrap_or(&self.path);
+        crash.dump_file = Some(dump_file.display().to_string());
+        crash
+    }
+}
+
+fn pstore_dumps(path: &Path, dumps: &mut Vec<CrashDump>) {
+    for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            // `systemd-pstore` archives records into a directory per boot
+            pstore_dumps(&path, dumps);
+            continue;
+        }
+        let file_name = entry.file_name();
+        if !file_name
+            .to_str()
+            .map_or(false, |x| x.starts_with("dmesg-"))
+        {
+            continue;
+        }
+        // Records are split into parts, and the first has the end of the log
+        let log = fs::read(&path).unwrap_or_default();
+        let header = log.split(|x| *x == b'\n').next().unwrap_or_default();
+        if !header.is_empty() && !header.ends_with(b"Part1") {
+            continue;
+        }
+        if let Some(time) = mtime(&path) {
+            dumps.push(CrashDump {
+                path,
+                hash: super::stable_hash(&log),
+                time,
+                dump_file: None,
+            });
+        }
+    }
+}
+
+// `/var/crash/<timestamp>/{dmesg,dump}.<timestamp>`
+fn kdump_dumps(path: &Path, dumps: &mut Vec<CrashDump>) {
+    for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+        let dir = entry.path();
+        let stamp = entry.file_name();
+        let stamp = match stamp.to_str() {
+            Some(stamp) if dir.is_dir() => stamp.to_string(),
+            _ => {
+                continue;
+            }
+        };
+        let path = dir.join(format!("dmesg.{}", stamp));
+        let dump_file = dir.join(format!("dump.{}", stamp));
+        if let (Ok(log), Some(time)) = (fs::read(&path), mtime(&path)) {
+            dumps.push(CrashDump {
+                path,
+                hash: super::stable_hash(&log),
+                time,
+                dump_file: Some(dump_file).filter(|x| x.exists()),
+            });
+        }
+    }
+}
+
+/// Crash logs from previous boots
+pub fn crash_dumps() -> Vec<CrashDump> {
+    let mut dumps = Vec::new();
+    pstore_dumps(Path::new("/sys/fs/pstore"), &mut dumps);
+    pstore_dumps(Path::new("/var/lib/systemd/pstore"), &mut dumps);
+    kdump_dumps(Path::new("/var/crash"), &mut dumps);
+    dumps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test::FakeDir;
+
+    fn parse(log: &str) -> Vec<LinuxDriverCrash> {
+        let mut parser = CrashParser::saved();
+        let mut crashes = Vec::new();
+        for line in log.lines() {
+            let (timestamp, message) = parse_saved_line(line);
+            parser.push(timestamp, message, &mut crashes);
+        }
+        parser.finish(&mut crashes);
+        crashes
+    }
+
+    #[test]
+    fn kmsg_blocks() {
+        let crashes = parse(
+            "<1>[  100.000001] BUG: kernel NULL pointer dereference, address: 0000000000000000
+<1>[  100.000002] #PF: supervisor read access in kernel mode
+<4>[  100.000003] Oops: 0000 [#1] PREEMPT SMP NOPTI
+<4>[  100.000004] CPU: 3 PID: 1234 Comm: insmod Tainted: G OE 5.15.0-43-generic #46-Ubuntu
+<4>[  100.000005] RIP: 0010:foo_init+0x12/0x34 [foo]
+<4>[  100.000006] ---[ end trace 0123456789abcdef ]---
+<4>[  200.000000] WARNING: CPU: 0 PID: 1 at drivers/foo.c:123 foo_probe+0x12/0x34
+<4>[  200.000001] CPU: 0 PID: 1 Comm: swapper/0 Not tainted 5.15.0-43-generic #46-Ubuntu
+<4>[  200.000002] ---[ end trace 0123456789abcdf0 ]---
+<1>[  300.000000] BUG: unable to handle page fault for address: ffffffffc0000000
+<6>[  400.000000] usb 1-1: new high-speed USB device number 2 using xhci_hcd
+<0>[  500.000000] Kernel panic - not syncing: Fatal exception",
+        );
+
+        assert_eq!(crashes.len(), 4);
+
+        // `BUG` followed by `Oops` is one crash
+        assert_eq!(crashes[0].panic.as_deref(), Some("Oops"));
+        assert_eq!(crashes[0].pid, Some(1234));
+        assert_eq!(crashes[0].command.as_deref(), Some("insmod"));
+        assert_eq!(crashes[0].kernel, "5.15.0-43-generic");
+        assert_eq!(crashes[0].up_time, Some(100));
+
+        assert_eq!(crashes[1].panic.as_deref(), Some("WARNING"));
+        assert_eq!(crashes[1].pid, Some(1));
+        assert_eq!(crashes[1].command.as_deref(), Some("swapper/0"));
+
+        // Ended by the next message being too late, without an end marker
+        assert_eq!(crashes[2].panic.as_deref(), Some("BUG"));
+        assert_eq!(crashes[2].pid, None);
+        assert_eq!(crashes[2].kernel, unknown());
+        assert_eq!(crashes[2].up_time, Some(300));
+
+        assert_eq!(crashes[3].panic.as_deref(), Some("Panic"));
+        assert_eq!(crashes[3].up_time, Some(500));
+    }
+
+    #[test]
+    fn kmsg_oops_panic() {
+        // A panic caused by an oops, as in a pstore record
+        let crashes = parse(
+            "<4>[   12.000001] Oops: 0002 [#1] SMP PTI
+<4>[   12.000002] CPU: 1 PID: 42 Comm: bash Not tainted 6.0.0 #1
+<0>[   12.000003] Kernel panic - not syncing: Fatal exception",
+        );
+
+        assert_eq!(crashes.len(), 1);
+        assert_eq!(crashes[0].panic.as_deref(), Some("Panic"));
+        assert_eq!(crashes[0].pid, Some(42));
+        assert_eq!(crashes[0].command.as_deref(), Some("bash"));
+        assert_eq!(crashes[0].kernel, "6.0.0");
+    }
+
+    #[test]
+    fn fake_pstore() {
+        let dir = FakeDir::new();
+
+        let log = "Oops#1 Part1
+<5>[    0.000000] Linux version 5.19.0-76051900-generic (jenkins@warp.pop-os.org)
+<4>[   12.000001] Oops: 0002 [#1] SMP PTI
+<4>[   12.000002] CPU: 1 PID: 42 Comm: bash Not tainted
+<4>[   12.000003] ---[ end trace 0000000000000000 ]---
+";
+        dir.write("pstore/dmesg-efi-166543215201001", log);
+        dir.write("pstore/dmesg-efi-166543215202001", "Oops#1 Part2\n");
+        // Archived by `systemd-pstore` in a later boot
+        dir.write("archive/0123456789abcdef/dmesg-efi-166543215201001", log);
+
+        let mut dumps = Vec::new();
+        pstore_dumps(&dir.path().join("pstore"), &mut dumps);
+        pstore_dumps(&dir.path().join("archive"), &mut dumps);
+        let crashes = dumps.iter().map(CrashDump::event).collect::<Vec<_>>();
+
+        assert_eq!(dumps.len(), 2);
+        assert_eq!(dumps[0].hash, dumps[1].hash);
+
+        assert_eq!(crashes[0].panic.as_deref(), Some("Oops"));
+        assert_eq!(crashes[0].pid, Some(42));
+        assert_eq!(crashes[0].kernel, "5.19.0-76051900-generic");
+        assert_eq!(crashes[0].up_time, Some(12));
+    }
+
+    #[test]
+    fn online_cpus() {
+        assert_eq!(cpu_count("0\n"), Some(1));
+        assert_eq!(cpu_count("0-7\n"), Some(8));
+        assert_eq!(cpu_count("0-3,6,8-9\n"), Some(7));
+        assert_eq!(cpu_count(""), None);
+    }
+}
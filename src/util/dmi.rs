@@ -3,7 +3,16 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use plain::Plain;
-use std::fs;
+use std::{fs, mem};
+
+// Type, length, and handle
+const HEADER_LEN: usize = 4;
+
+// Newer versions of a structure only append fields, so the length of the
+// formatted area in the header determines which layout a table has.
+fn has_layout<T>(table: &dmi::Table) -> bool {
+    usize::from(table.header.len) >= HEADER_LEN + mem::size_of::<T>()
+}
 
 pub fn dmi() -> Vec<dmi::Table> {
     if let Ok(data) = fs::read("/sys/firmware/dmi/tables/DMI") {
@@ -35,6 +44,66 @@ impl dmi::TableKind for CacheInfo21 {
     const KIND: u8 = 7;
 }
 
+impl CacheInfo21 {
+    /// Installed size in KiB
+    pub fn installed_size(&self) -> i64 {
+        // Bit 15 selects 64K granularity instead of 1K
+        let size = self.installed_size;
+        let granularity = if size & 0x8000 != 0 { 64 } else { 1 };
+        i64::from(size & 0x7FFF) * granularity
+    }
+}
+
+#[repr(packed)]
+#[derive(Clone, Default, Debug, Copy)]
+#[allow(dead_code)]
+pub struct CacheInfo31 {
+    pub socket: u8,
+    pub configuration: u16,
+    pub maximum_size: u16,
+    pub installed_size: u16,
+    pub supported_sram_type: u16,
+    pub current_sram_type: u16,
+    pub cache_speed: u8,
+    pub error_correction_type: u8,
+    pub system_cache_type: u8,
+    pub associativity: u8,
+    pub maximum_size2: u32,
+    pub installed_size2: u32,
+}
+
+unsafe impl Plain for CacheInfo31 {}
+
+impl dmi::TableKind for CacheInfo31 {
+    const KIND: u8 = 7;
+}
+
+impl CacheInfo31 {
+    pub fn get(table: &dmi::Table) -> Option<Self> {
+        has_layout::<Self>(table)
+            .then(|| table.get::<Self>())
+            .flatten()
+    }
+
+    /// Installed size in KiB, which may not fit in the 16-bit field
+    pub fn installed_size(&self) -> i64 {
+        // Bit 31 selects 64K granularity instead of 1K
+        let size = self.installed_size2;
+        let granularity = if size & 0x8000_0000 != 0 { 64 } else { 1 };
+        i64::from(size & 0x7FFF_FFFF) * granularity
+    }
+}
+
+/// Installed size in KiB of the cache `table` describes, from the newest
+/// layout the table has
+pub fn cache_installed_size(table: &dmi::Table) -> Option<i64> {
+    if let Some(info) = CacheInfo31::get(table) {
+        Some(info.installed_size())
+    } else {
+        Some(table.get::<CacheInfo21>()?.installed_size())
+    }
+}
+
 #[repr(packed)]
 #[derive(Clone, Default, Debug, Copy)]
 pub struct DmiUuid {
@@ -95,3 +164,471 @@ unsafe impl Plain for BiosInfo31 {}
 impl dmi::TableKind for BiosInfo31 {
     const KIND: u8 = 0;
 }
+
+// Names used by `lshw`, for bits 4-31
+static BIOS_CHARACTERISTICS: &[&str] = &[
+    "isa",
+    "mca",
+    "eisa",
+    "pci",
+    "pcmcia",
+    "pnp",
+    "apm",
+    "upgrade",
+    "shadowing",
+    "vlb",
+    "escd",
+    "cdboot",
+    "bootselect",
+    "socketedrom",
+    "pcmciaboot",
+    "edd",
+    "int13floppynec",
+    "int13floppytoshiba",
+    "int13floppy360",
+    "int13floppy1200",
+    "int13floppy720",
+    "int13floppy2880",
+    "int5printscreen",
+    "int9keyboard",
+    "int14serial",
+    "int17printer",
+    "int10video",
+    "pc98",
+];
+
+static BIOS_CHARACTERISTICS_EXT1: &[&str] = &[
+    "acpi",
+    "usb",
+    "agp",
+    "i2oboot",
+    "ls120boot",
+    "zipboot",
+    "ieee1394boot",
+    "smartbattery",
+];
+
+static BIOS_CHARACTERISTICS_EXT2: &[&str] = &[
+    "biosbootspecification",
+    "netboot",
+    "targetedcontent",
+    "uefi",
+    "virtualmachine",
+];
+
+impl BiosInfo31 {
+    pub fn capabilities(&self) -> Vec<String> {
+        let characteristics = self.characteristics;
+        let [ext1, ext2] = self.characteristics_extension_bytes;
+
+        // "BIOS Characteristics are not supported"
+        if characteristics & (1 << 3) != 0 {
+            return Vec::new();
+        }
+
+        let mut capabilities = Vec::new();
+        for (i, name) in BIOS_CHARACTERISTICS.iter().enumerate() {
+            if characteristics & (1 << (i + 4)) != 0 {
+                capabilities.push(name.to_string());
+            }
+        }
+        for (i, name) in BIOS_CHARACTERISTICS_EXT1.iter().enumerate() {
+            if ext1 & (1 << i) != 0 {
+                capabilities.push(name.to_string());
+            }
+        }
+        for (i, name) in BIOS_CHARACTERISTICS_EXT2.iter().enumerate() {
+            if ext2 & (1 << i) != 0 {
+                capabilities.push(name.to_string());
+            }
+        }
+        capabilities
+    }
+}
+
+#[repr(packed)]
+#[derive(Clone, Default, Debug, Copy)]
+pub struct OemStrings11 {
+    pub count: u8,
+}
+
+unsafe impl Plain for OemStrings11 {}
+
+impl dmi::TableKind for OemStrings11 {
+    const KIND: u8 = 11;
+}
+
+/// Major and minor version, from a 32-bit or 64-bit entry point
+pub fn smbios_version() -> Option<(u8, u8)> {
+    let entry_point = fs::read("/sys/firmware/dmi/tables/smbios_entry_point").ok()?;
+    if entry_point.starts_with(b"_SM3_") {
+        Some((*entry_point.get(7)?, *entry_point.get(8)?))
+    } else if entry_point.starts_with(b"_SM_") {
+        Some((*entry_point.get(6)?, *entry_point.get(7)?))
+    } else {
+        None
+    }
+}
+
+#[repr(packed)]
+#[derive(Clone, Default, Debug, Copy)]
+#[allow(dead_code)]
+pub struct ProcessorInfo30 {
+    pub socket_designation: u8,
+    pub processor_kind: u8,
+    pub processor_family: u8,
+    pub processor_manufacturer: u8,
+    pub processor_id: u64,
+    pub processor_version: u8,
+    pub voltage: u8,
+    pub external_clock: u16,
+    pub max_speed: u16,
+    pub current_speed: u16,
+    pub status: u8,
+    pub processor_upgrade: u8,
+    pub l1_cache_handle: u16,
+    pub l2_cache_handle: u16,
+    pub l3_cache_handle: u16,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub part_number: u8,
+    pub core_count: u8,
+    pub core_enabled: u8,
+    pub thread_count: u8,
+    pub processor_characteristics: u16,
+    pub processor_family2: u16,
+    pub core_count2: u16,
+    pub core_enabled2: u16,
+    pub thread_count2: u16,
+}
+
+unsafe impl Plain for ProcessorInfo30 {}
+
+impl dmi::TableKind for ProcessorInfo30 {
+    const KIND: u8 = 4;
+}
+
+impl ProcessorInfo30 {
+    pub fn get(table: &dmi::Table) -> Option<Self> {
+        has_layout::<Self>(table)
+            .then(|| table.get::<Self>())
+            .flatten()
+    }
+
+    /// Core count, enabled core count, and thread count, which are `0xFF` in
+    /// the 8-bit fields if there are more than 255
+    pub fn counts(&self) -> (i64, i64, i64) {
+        let count = |count: u8, count2: u16| {
+            if count == 0xFF {
+                i64::from(count2)
+            } else {
+                i64::from(count)
+            }
+        };
+        (
+            count(self.core_count, self.core_count2),
+            count(self.core_enabled, self.core_enabled2),
+            count(self.thread_count, self.thread_count2),
+        )
+    }
+}
+
+#[repr(packed)]
+#[derive(Clone, Default, Debug, Copy)]
+#[allow(dead_code)]
+pub struct MemoryDevice27 {
+    pub physical_memory_array_handle: u16,
+    pub memory_error_information_handle: u16,
+    pub total_width: u16,
+    pub data_width: u16,
+    pub size: u16,
+    pub form_factor: u8,
+    pub device_set: u8,
+    pub device_locator: u8,
+    pub bank_locator: u8,
+    pub memory_kind: u8,
+    pub type_detail: u16,
+    pub speed: u16,
+    pub manufacturer: u8,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub part_number: u8,
+    pub attributes: u8,
+    pub extended_size: u32,
+    pub configured_speed: u16,
+}
+
+unsafe impl Plain for MemoryDevice27 {}
+
+impl dmi::TableKind for MemoryDevice27 {
+    const KIND: u8 = 17;
+}
+
+impl MemoryDevice27 {
+    pub fn get(table: &dmi::Table) -> Option<Self> {
+        has_layout::<Self>(table)
+            .then(|| table.get::<Self>())
+            .flatten()
+    }
+}
+
+#[repr(packed)]
+#[derive(Clone, Default, Debug, Copy)]
+#[allow(dead_code)]
+pub struct MemoryDevice33 {
+    pub physical_memory_array_handle: u16,
+    pub memory_error_information_handle: u16,
+    pub total_width: u16,
+    pub data_width: u16,
+    pub size: u16,
+    pub form_factor: u8,
+    pub device_set: u8,
+    pub device_locator: u8,
+    pub bank_locator: u8,
+    pub memory_kind: u8,
+    pub type_detail: u16,
+    pub speed: u16,
+    pub manufacturer: u8,
+    pub serial_number: u8,
+    pub asset_tag: u8,
+    pub part_number: u8,
+    pub attributes: u8,
+    pub extended_size: u32,
+    pub configured_speed: u16,
+    pub minimum_voltage: u16,
+    pub maximum_voltage: u16,
+    pub configured_voltage: u16,
+    pub memory_technology: u8,
+    pub memory_operating_mode_capability: u16,
+    pub firmware_version: u8,
+    pub module_manufacturer_id: u16,
+    pub module_product_id: u16,
+    pub memory_subsystem_controller_manufacturer_id: u16,
+    pub memory_subsystem_controller_product_id: u16,
+    pub non_volatile_size: u64,
+    pub volatile_size: u64,
+    pub cache_size: u64,
+    pub logical_size: u64,
+    pub extended_speed: u32,
+    pub extended_configured_speed: u32,
+}
+
+unsafe impl Plain for MemoryDevice33 {}
+
+impl dmi::TableKind for MemoryDevice33 {
+    const KIND: u8 = 17;
+}
+
+impl MemoryDevice33 {
+    pub fn get(table: &dmi::Table) -> Option<Self> {
+        has_layout::<Self>(table)
+            .then(|| table.get::<Self>())
+            .flatten()
+    }
+}
+
+/// Size in MiB of the memory device `table` describes, with `size` from the
+/// 2.1 layout. `0` if nothing is installed.
+pub fn memory_size(table: &dmi::Table, size: u16) -> Option<i64> {
+    match size {
+        0xFFFF => None,
+        // Larger than 32GiB - 1MiB
+        0x7FFF => {
+            let extended_size = MemoryDevice27::get(table)?.extended_size;
+            Some(i64::from(extended_size & 0x7FFF_FFFF))
+        }
+        // In KiB
+        _ if size & 0x8000 != 0 => Some(i64::from(size & 0x7FFF) / 1024),
+        _ => Some(i64::from(size)),
+    }
+}
+
+// `0xFFFF` means the speed is in the 32-bit field added in 3.3
+fn memory_speed_value(speed: u16, extended_speed: Option<u32>) -> Option<i64> {
+    match speed {
+        0 => None,
+        0xFFFF => extended_speed
+            .map(|x| i64::from(x & 0x7FFF_FFFF))
+            .filter(|x| *x != 0),
+        _ => Some(i64::from(speed)),
+    }
+}
+
+/// Speed in MT/s of the memory device `table` describes, with `speed` from the
+/// 2.3 layout, or the configured speed if the maximum isn't known
+pub fn memory_speed(table: &dmi::Table, speed: u16) -> Option<i64> {
+    let info33 = MemoryDevice33::get(table);
+    memory_speed_value(speed, info33.map(|x| x.extended_speed)).or_else(|| {
+        let info27 = MemoryDevice27::get(table)?;
+        memory_speed_value(
+            info27.configured_speed,
+            info33.map(|x| x.extended_configured_speed),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Structure of `kind` and `len`, with `fields` written at their offsets
+    // in the specification, which count from the start of the header
+    fn table(kind: u8, len: u8, fields: &[(usize, &[u8])]) -> dmi::Table {
+        let mut data = vec![0; usize::from(len)];
+        data[0] = kind;
+        data[1] = len;
+        for (offset, bytes) in fields {
+            data[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        data.extend_from_slice(b"Fixture\0\0");
+        dmi::tables(&data).into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn cache_sizes() {
+        // Length of each version's layout, fields, and installed size in KiB
+        let cases: &[(u8, &[(usize, &[u8])], i64)] = &[
+            // 2.1, in 64K granularity
+            (0x13, &[(0x09, &0x8010u16.to_le_bytes())], 1024),
+            // 3.1, too large for the 16-bit field
+            (
+                0x1B,
+                &[
+                    (0x09, &0xFFFFu16.to_le_bytes()),
+                    (0x17, &0x8001_0000u32.to_le_bytes()),
+                ],
+                4 * 1024 * 1024,
+            ),
+            // 3.1, in 1K granularity
+            (
+                0x1B,
+                &[
+                    (0x09, &0x0200u16.to_le_bytes()),
+                    (0x17, &0x0000_0200u32.to_le_bytes()),
+                ],
+                512,
+            ),
+        ];
+        for (len, fields, size) in cases {
+            let table = table(7, *len, fields);
+            assert_eq!(cache_installed_size(&table), Some(*size), "{:?}", fields);
+        }
+    }
+
+    #[test]
+    fn memory_sizes_and_speeds() {
+        // Length of each version's layout, fields, and size in MiB and speed
+        // in MT/s
+        let cases: &[(u8, &[(usize, &[u8])], Option<i64>, Option<i64>)] = &[
+            // 2.1, without speeds
+            (0x15, &[(0x0C, &2048u16.to_le_bytes())], Some(2048), None),
+            // 2.1, in KiB
+            (0x15, &[(0x0C, &0x8400u16.to_le_bytes())], Some(1), None),
+            // 2.3, too large for the 16-bit field but without the extended one
+            (
+                0x1B,
+                &[
+                    (0x0C, &0x7FFFu16.to_le_bytes()),
+                    (0x15, &3200u16.to_le_bytes()),
+                ],
+                None,
+                Some(3200),
+            ),
+            // 2.7, with the extended size
+            (
+                0x22,
+                &[
+                    (0x0C, &0x7FFFu16.to_le_bytes()),
+                    (0x1C, &65536u32.to_le_bytes()),
+                    (0x15, &3200u16.to_le_bytes()),
+                ],
+                Some(65536),
+                Some(3200),
+            ),
+            // 2.7, unknown speed, so the configured speed
+            (
+                0x22,
+                &[
+                    (0x0C, &8192u16.to_le_bytes()),
+                    (0x20, &2933u16.to_le_bytes()),
+                ],
+                Some(8192),
+                Some(2933),
+            ),
+            // 2.7, speed only in the extended field, which it doesn't have
+            (
+                0x22,
+                &[
+                    (0x0C, &8192u16.to_le_bytes()),
+                    (0x15, &0xFFFFu16.to_le_bytes()),
+                ],
+                Some(8192),
+                None,
+            ),
+            // 3.3, with the extended speed
+            (
+                0x5C,
+                &[
+                    (0x0C, &16384u16.to_le_bytes()),
+                    (0x15, &0xFFFFu16.to_le_bytes()),
+                    (0x54, &8400u32.to_le_bytes()),
+                ],
+                Some(16384),
+                Some(8400),
+            ),
+            // 3.3, with the extended configured speed
+            (
+                0x5C,
+                &[
+                    (0x0C, &16384u16.to_le_bytes()),
+                    (0x20, &0xFFFFu16.to_le_bytes()),
+                    (0x58, &7200u32.to_le_bytes()),
+                ],
+                Some(16384),
+                Some(7200),
+            ),
+        ];
+        for (len, fields, size, speed) in cases {
+            // From the 2.1 and 2.3 layouts
+            let field16 = |offset| {
+                fields
+                    .iter()
+                    .find(|(x, _)| *x == offset)
+                    .map_or(0, |(_, bytes)| u16::from_le_bytes([bytes[0], bytes[1]]))
+            };
+            let table = table(17, *len, fields);
+            assert_eq!(memory_size(&table, field16(0x0C)), *size, "{:?}", fields);
+            assert_eq!(memory_speed(&table, field16(0x15)), *speed, "{:?}", fields);
+        }
+    }
+
+    #[test]
+    fn processor_counts() {
+        // 3.0, with more than 255 of each
+        let processor = table(
+            4,
+            0x30,
+            &[
+                (0x23, &[0xFF, 0xFF, 0xFF]),
+                (0x2A, &300u16.to_le_bytes()),
+                (0x2C, &290u16.to_le_bytes()),
+                (0x2E, &600u16.to_le_bytes()),
+            ],
+        );
+        assert_eq!(
+            ProcessorInfo30::get(&processor).unwrap().counts(),
+            (300, 290, 600)
+        );
+
+        // 3.0, with few enough for the 8-bit fields
+        let processor = table(4, 0x30, &[(0x23, &[8, 8, 16])]);
+        assert_eq!(
+            ProcessorInfo30::get(&processor).unwrap().counts(),
+            (8, 8, 16)
+        );
+
+        // 2.6, without the 16-bit fields
+        let processor = table(4, 0x2A, &[(0x23, &[8, 8, 16])]);
+        assert!(ProcessorInfo30::get(&processor).is_none());
+    }
+}
@@ -80,7 +80,6 @@ impl DrmDevice {
             .copied()
     }
 
-    #[allow(dead_code)]
     pub fn connector_edid(&self, connector: &drm::control::connector::Info) -> Option<EDIDHeader> {
         let properties = self.get_properties(connector.handle()).ok()?;
         let (handles, values) = properties.as_props_and_values();
@@ -90,7 +89,7 @@ impl DrmDevice {
                 let bytes = self.get_property_blob(*raw_value).ok()?;
                 let mut header = EDIDHeader::default();
                 plain::copy_from_bytes(&mut header, &bytes).ok()?;
-                return Some(header);
+                return Some(header).filter(EDIDHeader::is_valid);
             }
         }
         None
@@ -123,3 +122,29 @@ pub struct EDIDHeader {
 }
 
 unsafe impl Plain for EDIDHeader {}
+
+impl EDIDHeader {
+    const MAGIC: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+    fn is_valid(&self) -> bool {
+        self.magic == Self::MAGIC
+    }
+
+    /// Three letter PNP ID, like `AUO`
+    pub fn manufacturer(&self) -> String {
+        let value = u16::from_be_bytes(self.manufacturer);
+        [10, 5, 0]
+            .iter()
+            .map(|shift| (b'A' - 1 + ((value >> shift) & 0x1f) as u8) as char)
+            .collect()
+    }
+
+    pub fn product_code(&self) -> u16 {
+        u16::from_le(self.product_code)
+    }
+
+    /// `None` if not set by the manufacturer
+    pub fn serial_number(&self) -> Option<u32> {
+        Some(u32::from_le(self.serial_number)).filter(|x| *x != 0)
+    }
+}
@@ -0,0 +1,80 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use nix::{
+    sys::socket::{socket, AddressFamily, SockFlag, SockType},
+    unistd,
+};
+use std::fmt::Write;
+
+// From `linux/sockios.h`, `linux/ethtool.h`, and `linux/netdevice.h`
+const SIOCETHTOOL: u32 = 0x8946;
+const ETHTOOL_GPERMADDR: u32 = 0x20;
+const IFNAMSIZ: usize = 16;
+const MAX_ADDR_LEN: usize = 32;
+
+// `struct ethtool_perm_addr`, with room for the longest address
+#[repr(C)]
+struct PermAddr {
+    cmd: u32,
+    size: u32,
+    data: [u8; MAX_ADDR_LEN],
+}
+
+// `struct ifreq`, with only the `ifr_data` member of its union
+#[repr(C)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    data: *mut PermAddr,
+    // Rest of the union, which is larger than a pointer
+    _pad: [u8; 16],
+}
+
+nix::ioctl_readwrite_bad!(siocethtool, SIOCETHTOOL, IfReq);
+
+/// Address the network interface `name` was manufactured with, like
+/// `ethtool -P`, which it keeps even if the current one is random or set by
+/// userspace. `None` if the driver doesn't report one.
+pub fn permanent_address(name: &str) -> Option<String> {
+    // Leaving room for the nul terminator
+    if name.len() >= IFNAMSIZ {
+        return None;
+    }
+    let mut ifr_name = [0; IFNAMSIZ];
+    ifr_name[..name.len()].copy_from_slice(name.as_bytes());
+
+    let mut addr = PermAddr {
+        cmd: ETHTOOL_GPERMADDR,
+        size: MAX_ADDR_LEN as u32,
+        data: [0; MAX_ADDR_LEN],
+    };
+    let mut req = IfReq {
+        name: ifr_name,
+        data: &mut addr,
+        _pad: [0; 16],
+    };
+
+    let fd = socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .ok()?;
+    let res = unsafe { siocethtool(fd, &mut req) };
+    let _ = unistd::close(fd);
+    res.ok()?;
+
+    // All zeros if the driver has no permanent address
+    let data = addr.data.get(..addr.size as usize)?;
+    if data.iter().all(|x| *x == 0) {
+        return None;
+    }
+    let mut address = String::new();
+    for i in data {
+        write!(address, "{:02x}:", i).ok()?;
+    }
+    address.pop();
+    Some(address)
+}
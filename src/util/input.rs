@@ -0,0 +1,56 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{fs, os::unix::io::AsRawFd};
+
+// From `linux/input-event-codes.h`
+const SW_HEADPHONE_INSERT: usize = 0x02;
+const SW_MICROPHONE_INSERT: usize = 0x04;
+const SW_LINEOUT_INSERT: usize = 0x06;
+const SW_JACK_PHYSICAL_INSERT: usize = 0x07;
+const SW_MAX: usize = 0x10;
+
+const JACK_SWITCHES: &[usize] = &[
+    SW_HEADPHONE_INSERT,
+    SW_MICROPHONE_INSERT,
+    SW_LINEOUT_INSERT,
+    SW_JACK_PHYSICAL_INSERT,
+];
+
+nix::ioctl_read_buf!(eviocgsw, b'E', 0x1b, u8);
+
+fn test_bit(bits: &[u8], bit: usize) -> bool {
+    bits.get(bit / 8)
+        .map_or(false, |x| x & (1 << (bit % 8)) != 0)
+}
+
+/// Whether `device` is an evdev node for an audio jack, like the ones ALSA
+/// creates for each jack control of a sound card.
+pub fn is_jack(device: &udev::Device) -> bool {
+    if !device
+        .sysname()
+        .to_str()
+        .map_or(false, |x| x.starts_with("event"))
+    {
+        return false;
+    }
+
+    // Hex words, most significant first; all switches fit in the last one
+    let caps = device
+        .parent()
+        .and_then(|x| Some(x.attribute_value("capabilities/sw")?.to_str()?.to_string()));
+    let caps = caps
+        .as_deref()
+        .and_then(|x| x.split_whitespace().last())
+        .and_then(|x| u64::from_str_radix(x, 16).ok())
+        .unwrap_or(0);
+    JACK_SWITCHES.iter().any(|x| caps & (1 << x) != 0)
+}
+
+/// Whether anything is plugged in to the jack `file` is opened for
+pub fn jack_inserted(file: &fs::File) -> Option<bool> {
+    let mut bits = [0; SW_MAX / 8 + 1];
+    unsafe { eviocgsw(file.as_raw_fd(), &mut bits) }.ok()?;
+    Some(JACK_SWITCHES.iter().any(|x| test_bit(&bits, *x)))
+}
@@ -40,4 +40,30 @@ pub fn lock_file_or_panic(path: &str) -> Lock {
     Lock(file)
 }
 
+/// Like `lock_file_or_panic`, but returns `None` if the lock can't be taken
+pub fn try_lock_file(path: &str) -> Option<Lock> {
+    let file = fs::File::create(path).ok()?;
+    setlk(&file).ok()?;
+    Some(Lock(file))
+}
+
+/// Whether another process holds a lock on the file
+pub fn is_locked(path: &str) -> bool {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => {
+            return false;
+        }
+    };
+    let mut flock = libc::flock {
+        l_type: libc::F_WRLCK as _,
+        l_whence: libc::SEEK_SET as _,
+        l_start: 0,
+        l_len: 0,
+        l_pid: 0,
+    };
+    fcntl(file.as_raw_fd(), FcntlArg::F_GETLK(&mut flock)).is_ok()
+        && flock.l_type != libc::F_UNLCK as _
+}
+
 pub struct Lock(fs::File);
@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde_json::Value;
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use super::bus::BusMonitor;
+
+const SLEEP_MATCH: &str = "type='signal',sender='org.freedesktop.login1',\
+    interface='org.freedesktop.login1.Manager',member='PrepareForSleep'";
+
+/// Watches for logind's `PrepareForSleep` signal
+pub struct SleepMonitor(BusMonitor);
+
+impl SleepMonitor {
+    pub fn new() -> io::Result<Self> {
+        BusMonitor::new(SLEEP_MATCH).map(Self)
+    }
+
+    /// Argument of each signal received: `true` before suspend, and `false`
+    /// after resume
+    pub fn read(&mut self) -> Vec<bool> {
+        self.0
+            .read()
+            .iter()
+            .filter_map(|x| x.pointer("/payload/data/0").and_then(Value::as_bool))
+            .collect()
+    }
+
+    /// See `BusMonitor::exited`
+    pub fn exited(&self) -> Option<Duration> {
+        self.0.exited()
+    }
+}
+
+impl AsRawFd for SleepMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Delays suspend until dropped, with a `systemd-inhibit` child process, so
+/// writes can be flushed after `PrepareForSleep`
+pub struct SleepInhibitor(Child);
+
+impl SleepInhibitor {
+    pub fn new() -> io::Result<Self> {
+        // Holds the lock until `cat` sees EOF on stdin
+        let child = Command::new("systemd-inhibit")
+            .args([
+                "--what=sleep",
+                "--mode=delay",
+                "--who=hp-vendor",
+                "--why=Write pending telemetry",
+                "cat",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        Ok(Self(child))
+    }
+}
+
+impl Drop for SleepInhibitor {
+    fn drop(&mut self) {
+        drop(self.0.stdin.take());
+        let _ = self.0.wait();
+    }
+}
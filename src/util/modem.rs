@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde_json::Value;
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::PathBuf,
+    process::Command,
+    time::Duration,
+};
+
+use super::bus::BusMonitor;
+
+const MM_SERVICE: &str = "org.freedesktop.ModemManager1";
+const MM_PATH: &str = "/org/freedesktop/ModemManager1";
+const MM_MODEM_INTERFACE: &str = "org.freedesktop.ModemManager1.Modem";
+const MM_SIM_INTERFACE: &str = "org.freedesktop.ModemManager1.Sim";
+
+const MODEM_MATCH: &str = "type='signal',sender='org.freedesktop.ModemManager1',\
+    interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',\
+    arg0='org.freedesktop.ModemManager1.Modem'";
+
+// Variants are represented as `{"type": ..., "data": ...}`
+fn busctl(args: &[&str]) -> Option<Value> {
+    let output = Command::new("busctl")
+        .arg("--json=short")
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+// Hash of the SIM's ICCID, to tell SIMs apart without storing it
+fn sim_id(path: &str) -> Option<String> {
+    // No SIM is represented as `/`
+    if path == "/" {
+        return None;
+    }
+    let iccid = busctl(&[
+        "get-property",
+        MM_SERVICE,
+        path,
+        MM_SIM_INTERFACE,
+        "SimIdentifier",
+    ])?;
+    let iccid = iccid.pointer("/data")?.as_str()?;
+    Some(format!("{:016x}", super::stable_hash(iccid.as_bytes())))
+}
+
+// Sysfs path of each modem's device, and the ID of its SIM
+fn modems() -> Vec<(String, Option<String>)> {
+    let objects = match busctl(&[
+        "call",
+        MM_SERVICE,
+        MM_PATH,
+        "org.freedesktop.DBus.ObjectManager",
+        "GetManagedObjects",
+    ]) {
+        Some(objects) => objects,
+        // ModemManager isn't running
+        None => {
+            return Vec::new();
+        }
+    };
+
+    let mut modems = Vec::new();
+    for interfaces in objects
+        .pointer("/data/0")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|x| x.values())
+    {
+        let modem = match interfaces.get(MM_MODEM_INTERFACE) {
+            Some(modem) => modem,
+            None => {
+                continue;
+            }
+        };
+        let device = modem.pointer("/Device/data").and_then(Value::as_str);
+        let sim = modem.pointer("/Sim/data").and_then(Value::as_str);
+        if let Some(device) = device {
+            modems.push((device.to_string(), sim.and_then(sim_id)));
+        }
+    }
+    modems
+}
+
+/// Sysfs paths of `wwan` devices
+pub fn wwan_devices() -> Vec<PathBuf> {
+    let mut enumerator = match udev::Enumerator::new() {
+        Ok(enumerator) => enumerator,
+        Err(_) => {
+            return Vec::new();
+        }
+    };
+    if enumerator.match_subsystem("wwan").is_err() {
+        return Vec::new();
+    }
+    enumerator
+        .scan_devices()
+        .into_iter()
+        .flatten()
+        // Ports are children of the `wwan_dev`
+        .filter(|x| x.devtype().and_then(|x| x.to_str()) == Some("wwan_dev"))
+        .map(|x| x.syspath().to_owned())
+        .collect()
+}
+
+/// IDs of the SIMs in the modems of `wwan` devices, which are stable for a
+/// given SIM
+pub fn sims() -> Vec<String> {
+    // Queried once for all devices
+    let modems = modems();
+    if modems.is_empty() {
+        return Vec::new();
+    }
+    wwan_devices()
+        .iter()
+        .filter_map(|syspath| {
+            let (_, sim) = modems.iter().find(|(path, _)| syspath.starts_with(path))?;
+            sim.clone()
+        })
+        .collect()
+}
+
+/// Watches for a SIM being inserted or removed, which only ModemManager
+/// reports, by a change to the `Sim` property of a modem
+pub struct SimMonitor(BusMonitor);
+
+impl SimMonitor {
+    pub fn new() -> io::Result<Self> {
+        BusMonitor::new(MODEM_MATCH).map(Self)
+    }
+
+    /// Whether the SIM of any modem changed since the last call
+    pub fn read(&mut self) -> bool {
+        // Arguments are the interface, changed properties, and invalidated
+        // properties
+        self.0
+            .read()
+            .iter()
+            .any(|x| x.pointer("/payload/data/1/Sim").is_some())
+    }
+
+    /// See `BusMonitor::exited`
+    pub fn exited(&self) -> Option<Duration> {
+        self.0.exited()
+    }
+}
+
+impl AsRawFd for SimMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Fields of a kernel module, as shown by `modinfo`
+#[derive(Debug, Default)]
+pub struct ModInfo(HashMap<String, String>);
+
+impl ModInfo {
+    // Repeated fields, like `author`, are joined with newlines like `modinfo -F`
+    fn insert(&mut self, key: &str, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.0
+            .entry(key.to_string())
+            .and_modify(|x| {
+                x.push('\n');
+                x.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0.get(key).cloned()
+    }
+}
+
+/// Runs `modinfo` once for a loadable module
+pub fn modinfo(name: &str) -> Option<ModInfo> {
+    let output = Command::new("/usr/sbin/modinfo").arg(name).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let mut info = ModInfo::default();
+    for line in stdout.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            // Skip continuations of multi-line values
+            if !key.is_empty() && !key.contains(char::is_whitespace) {
+                info.insert(key, value.trim());
+            }
+        }
+    }
+    Some(info)
+}
+
+pub fn modules_dir(release: &str) -> PathBuf {
+    Path::new("/lib/modules").join(release)
+}
+
+/// Modules built in to the kernel, from `modules.builtin`, with fields from
+/// `modules.builtin.modinfo`
+pub fn builtin_modules(release: &str) -> Vec<(String, ModInfo)> {
+    let dir = modules_dir(release);
+
+    // `name.key=value`, separated by nulls
+    let mut infos = HashMap::<String, ModInfo>::new();
+    let modinfo = fs::read(dir.join("modules.builtin.modinfo")).unwrap_or_default();
+    for entry in modinfo.split(|x| *x == 0) {
+        let entry = String::from_utf8_lossy(entry);
+        if let Some((name, field)) = entry.split_once('.') {
+            if let Some((key, value)) = field.split_once('=') {
+                infos
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert(key, value.trim());
+            }
+        }
+    }
+
+    // Paths like `kernel/drivers/acpi/button.ko`
+    let builtin = fs::read_to_string(dir.join("modules.builtin")).unwrap_or_default();
+    builtin
+        .lines()
+        .filter_map(|line| {
+            let file_name = line.rsplit('/').next()?;
+            let name = file_name.strip_suffix(".ko")?.replace('-', "_");
+            let info = infos.remove(&name).unwrap_or_default();
+            Some((name, info))
+        })
+        .collect()
+}
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{ffi::OsStr, process::Command};
+use std::{convert::TryInto, ffi::OsStr, process::Command};
 
 #[derive(Debug)]
 pub struct ControllerId {
@@ -11,6 +11,8 @@ pub struct ControllerId {
     pub ver: i64,
     pub wctemp: i64,
     pub cctemp: i64,
+    // Total NVM capacity, in bytes; may be 0 if not reported
+    pub tnvmcap: u128,
     // Ignoring fields that aren't useful
 }
 
@@ -36,22 +38,49 @@ impl ControllerId {
         let cctemp = bytes.get(268..=269)?;
         let cctemp = i64::from(u16::from_le_bytes([cctemp[0], cctemp[1]]));
 
+        let tnvmcap = bytes.get(280..=295)?;
+        let tnvmcap = u128::from_le_bytes(tnvmcap.try_into().ok()?);
+
         Some(Self {
             sn,
             ver,
             wctemp,
             cctemp,
+            tnvmcap,
         })
     }
 }
 
-#[allow(dead_code)]
+#[derive(serde::Deserialize)]
+pub struct LbaFormat {
+    // Log2 of LBA data size
+    ds: u32,
+}
+
 #[derive(serde::Deserialize)]
 pub struct NamespaceId {
-    nuse: i64,
+    nsze: u64,
+    nuse: u64,
+    flbas: u8,
+    lbafs: Vec<LbaFormat>,
     // Ignoring fields that aren't useful
 }
 
+impl NamespaceId {
+    fn lba_size(&self) -> Option<u64> {
+        let ds = self.lbafs.get(usize::from(self.flbas & 0xf))?.ds;
+        1u64.checked_shl(ds)
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.nsze.checked_mul(self.lba_size()?)
+    }
+
+    pub fn used(&self) -> Option<u64> {
+        self.nuse.checked_mul(self.lba_size()?)
+    }
+}
+
 // TODO: what should be optional?
 // For parsing JSON output of `nvme smart-log`
 // See also `struct nvme_smart_log`
@@ -138,7 +167,16 @@ pub fn controller_id<S: AsRef<OsStr>>(path: S) -> Option<ControllerId> {
     ControllerId::from_bytes(&nvme_cmd_binary("id-ctrl", path)?)
 }
 
-#[allow(dead_code)]
+// Namespace 1 of the controller at `path`; other namespaces are uncommon
+// outside of servers
 pub fn namespace_id<S: AsRef<OsStr>>(path: S) -> Option<NamespaceId> {
-    nvme_cmd("id-ns", path)
+    let stdout = Command::new("nvme")
+        .arg("id-ns")
+        .arg(&path)
+        .arg("--namespace-id=1")
+        .arg("--output-format=json")
+        .output()
+        .ok()?
+        .stdout;
+    serde_json::from_slice(&stdout).ok()
 }
@@ -6,6 +6,11 @@
 
 use std::{collections::HashSet, fmt::Write, path::Path};
 
+const PCI_COMMAND: usize = 0x04;
+const PCI_COMMAND_MASTER: u8 = 0x04;
+const PCI_STATUS: usize = 0x06;
+const PCI_STATUS_CAP_LIST: u8 = 0x10;
+const PCI_CAPABILITY_LIST: usize = 0x34;
 const PCI_EXT_CAP_ID_DSN: u16 = 0x03;
 
 pub fn pcie_dsn<P: AsRef<Path>>(path: P) -> Option<String> {
@@ -33,3 +38,80 @@ pub fn pcie_dsn<P: AsRef<Path>>(path: P) -> Option<String> {
     }
     None
 }
+
+/// Capabilities from PCI config space, named like `lshw`
+pub fn pci_capabilities<P: AsRef<Path>>(path: P) -> Vec<&'static str> {
+    let data = match std::fs::read(path.as_ref()) {
+        Ok(data) => data,
+        Err(_) => {
+            return Vec::new();
+        }
+    };
+    let mut capabilities = Vec::new();
+    if data
+        .get(PCI_COMMAND)
+        .map_or(false, |x| x & PCI_COMMAND_MASTER != 0)
+    {
+        capabilities.push("bus_master");
+    }
+    if data
+        .get(PCI_STATUS)
+        .map_or(false, |x| x & PCI_STATUS_CAP_LIST != 0)
+    {
+        capabilities.push("cap_list");
+        let mut been = HashSet::new();
+        let mut offset = data
+            .get(PCI_CAPABILITY_LIST)
+            .map_or(0, |x| *x as usize & !0b11);
+        while offset != 0 && been.insert(offset) {
+            let (cap_id, next) = match (data.get(offset), data.get(offset + 1)) {
+                (Some(cap_id), Some(next)) => (*cap_id, *next as usize & !0b11),
+                _ => break,
+            };
+            match cap_id {
+                0x01 => capabilities.push("pm"),
+                0x05 => capabilities.push("msi"),
+                0x10 => capabilities.push("pciexpress"),
+                0x11 => capabilities.push("msix"),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+    capabilities
+}
+
+/// Parses `current_link_speed`, like `8.0 GT/s PCIe`, to MT/s
+pub fn pcie_link_speed(speed: &str) -> Option<i64> {
+    let gts = speed.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some((gts * 1000.) as i64)
+}
+
+pub const PCI_VENDOR_ID_AMD: &str = "0x1002";
+pub const PCI_VENDOR_ID_INTEL: &str = "0x8086";
+pub const PCI_VENDOR_ID_NVIDIA: &str = "0x10de";
+
+/// Short vendor name for common PCI vendors, falling back to hwdb
+pub fn pci_vendor_name(device: &udev::Device) -> Option<String> {
+    let vendor = device.attribute_value("vendor")?.to_str()?;
+    Some(match vendor {
+        PCI_VENDOR_ID_AMD => "AMD".to_string(),
+        PCI_VENDOR_ID_INTEL => "Intel".to_string(),
+        PCI_VENDOR_ID_NVIDIA => "Nvidia".to_string(),
+        _ => device
+            .property_value("ID_VENDOR_FROM_DATABASE")?
+            .to_str()?
+            .to_string(),
+    })
+}
+
+/// Splits hwdb model name, like `GM107GLM [Quadro M1000M]`, into chip name and model
+pub fn pci_model_name(device: &udev::Device) -> Option<(Option<String>, String)> {
+    let name = device.property_value("ID_MODEL_FROM_DATABASE")?.to_str()?;
+    if let Some((chip, model)) = name.split_once(" [") {
+        let model = model.strip_suffix(']').unwrap_or(model);
+        Some((Some(chip.to_string()), model.to_string()))
+    } else {
+        Some((None, name.to_string()))
+    }
+}
@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::collections::HashMap;
+
+use super::hp_vendor_conf;
+use crate::read_file;
+
+/// Logical thermal zones, as used in `ThermalSummary`
+pub const ZONES: &[&str] = &["cpu", "gpu", "ext", "loc", "bat", "chg"];
+
+/// Where a sensor is read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// `<input>_input` of the hwmon device with this `name`, written like
+    /// `hwmon:acpitz:temp1`
+    Hwmon { name: String, input: String },
+    /// `temp` of the thermal zone with this `type`, written like
+    /// `thermal_zone:x86_pkg_temp`
+    ThermalZone { type_: String },
+}
+
+impl Source {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split(':');
+        let source = match (parts.next()?, parts.next(), parts.next()) {
+            ("hwmon", Some(name), Some(input)) => Self::Hwmon {
+                name: name.to_string(),
+                input: input.to_string(),
+            },
+            ("thermal_zone", Some(type_), None) => Self::ThermalZone {
+                type_: type_.to_string(),
+            },
+            _ => {
+                return None;
+            }
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(source)
+    }
+}
+
+// Sources by zone, for each supported board
+fn builtin_profile(board_name: &str) -> &'static [(&'static str, &'static str)] {
+    match board_name {
+        // HP Dev One
+        "8A78" => &[
+            ("cpu", "hwmon:acpitz:temp1"),
+            ("gpu", "hwmon:acpitz:temp2"),
+            ("ext", "hwmon:acpitz:temp3"),
+            ("loc", "hwmon:acpitz:temp4"),
+            ("bat", "hwmon:acpitz:temp5"),
+            ("chg", "hwmon:acpitz:temp6"),
+            ("fan", "hwmon:hp_vendor:fan1"),
+        ],
+        _ => &[],
+    }
+}
+
+/// Sensor for each zone in `ZONES`, and `fan`
+#[derive(Debug, Default)]
+pub struct Profile(HashMap<String, Source>);
+
+impl Profile {
+    /// Built-in profile for the board, with overrides from the `[sensors]`
+    /// table of `/etc/hp-vendor.conf`. An empty string removes a zone.
+    pub fn load() -> Self {
+        let board_name: String = read_file("/sys/class/dmi/id/board_name").unwrap_or_default();
+
+        let mut sources = HashMap::new();
+        for (zone, source) in builtin_profile(&board_name) {
+            if let Some(source) = Source::parse(source) {
+                sources.insert(zone.to_string(), source);
+            }
+        }
+
+        for (zone, source) in &hp_vendor_conf().sensors {
+            if source.is_empty() {
+                sources.remove(zone);
+            } else if let Some(source) = Source::parse(source) {
+                sources.insert(zone.clone(), source);
+            } else {
+                eprintln!("Invalid source `{}` for sensor `{}`", source, zone);
+            }
+        }
+
+        Self(sources)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
@@ -2,12 +2,32 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use nix::{sys::time::TimeValLike, time::ClockId};
+use std::collections::{BTreeMap, HashMap};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
+use super::{
+    hp_vendor_conf,
+    profile::{Profile, Source, ZONES},
+    SamplingConf,
+};
 use crate::{event, read_file, unknown};
 
-// Also used for BatteryLife
-pub const TEMP_SAMPLE_SECONDS: i64 = 60;
+// Default interval, which samples stored without a `SampleClock` used
+const TEMP_SAMPLE_SECONDS: i64 = 60;
+
+// Gap between samples without a `SampleClock`, in seconds, treated as the
+// system being suspended or off
+const MAX_SAMPLE_GAP: i64 = 2 * TEMP_SAMPLE_SECONDS;
+
+// Time suspended between samples, in milliseconds, allowed for clock skew
+const MAX_SUSPEND_MILLIS: i64 = 1000;
+
+const DEFAULT_STRESS_TEMP: i64 = 80;
+
+/// Length of time summarized by each `ThermalSummary` and `BatteryLife`, in
+/// seconds, independent of the sample interval
+pub const SUMMARY_SECONDS: i64 = 100 * 60;
 
 fn unix_time() -> i64 {
     OffsetDateTime::now_utc().unix_timestamp()
@@ -20,15 +40,126 @@ fn format_unix_time(time: i64) -> String {
         .unwrap()
 }
 
+fn clock_millis(clock: ClockId) -> Option<i64> {
+    Some(nix::time::clock_gettime(clock).ok()?.num_milliseconds())
+}
+
+/// Clocks read with a sample, to tell how long the system was up between
+/// samples. Fields are `None` for samples stored before they were recorded.
+#[derive(Debug, Default)]
+pub struct SampleClock {
+    pub boot_id: Option<String>,
+    /// `CLOCK_MONOTONIC` in milliseconds, which stops while suspended
+    pub monotonic: Option<i64>,
+    /// `CLOCK_BOOTTIME` in milliseconds, which includes time suspended
+    pub boottime: Option<i64>,
+}
+
+impl SampleClock {
+    fn now() -> Self {
+        Self {
+            boot_id: super::boot::boot_id(),
+            monotonic: clock_millis(ClockId::CLOCK_MONOTONIC),
+            boottime: clock_millis(ClockId::CLOCK_BOOTTIME),
+        }
+    }
+
+    fn same_boot(&self, other: &Self) -> Option<bool> {
+        Some(self.boot_id.as_ref()? == other.boot_id.as_ref()?)
+    }
+}
+
+// A sample with the time it was taken, in seconds since the epoch
+trait Sample {
+    fn clock(&self) -> &SampleClock;
+    fn time(&self) -> i64;
+}
+
+// Seconds the system was up between consecutive samples. Without monotonic
+// times, a gap over `MAX_SAMPLE_GAP` is assumed to be suspend or shutdown.
+fn up_time(prev: &impl Sample, next: &impl Sample) -> i64 {
+    let (prev_clock, next_clock) = (prev.clock(), next.clock());
+    match prev_clock.same_boot(next_clock) {
+        Some(true) => {
+            if let (Some(a), Some(b)) = (prev_clock.monotonic, next_clock.monotonic) {
+                return (b - a) / 1000;
+            }
+        }
+        Some(false) => {
+            return 0;
+        }
+        None => {}
+    }
+    let elapsed = next.time() - prev.time();
+    if (0..=MAX_SAMPLE_GAP).contains(&elapsed) {
+        elapsed
+    } else {
+        0
+    }
+}
+
+// Whether sampling continued without a reboot or suspend between samples
+fn continuous(prev: &impl Sample, next: &impl Sample) -> bool {
+    let (prev_clock, next_clock) = (prev.clock(), next.clock());
+    match prev_clock.same_boot(next_clock) {
+        Some(true) => {
+            if let (Some(a), Some(b), Some(c), Some(d)) = (
+                prev_clock.boottime,
+                next_clock.boottime,
+                prev_clock.monotonic,
+                next_clock.monotonic,
+            ) {
+                return (b - a) - (d - c) <= MAX_SUSPEND_MILLIS;
+            }
+        }
+        Some(false) => {
+            return false;
+        }
+        None => {}
+    }
+    next.time() - prev.time() <= MAX_SAMPLE_GAP
+}
+
+// Each sample but the last, with the seconds the system was up until the next
+fn up_times<T: Sample>(samples: &[T]) -> impl Iterator<Item = (&T, i64)> {
+    samples.windows(2).map(|x| (&x[0], up_time(&x[0], &x[1])))
+}
+
 #[derive(Debug)]
 pub struct Temps {
-    pub cpu: i64,
-    pub ext: i64,
-    pub bat: i64,
-    pub chg: i64,
+    /// Degrees Celsius, for zones in `ZONES` that have a sensor
+    pub zones: BTreeMap<String, i64>,
     pub on_ac: bool,
     pub charging: bool,
     pub time: i64,
+    pub clock: SampleClock,
+}
+
+impl Sample for Temps {
+    fn clock(&self) -> &SampleClock {
+        &self.clock
+    }
+
+    fn time(&self) -> i64 {
+        self.time
+    }
+}
+
+#[derive(Debug)]
+pub struct Fan {
+    pub rpm: i64,
+    pub time: i64,
+    pub clock: SampleClock,
+}
+
+impl Sample for Fan {
+    fn clock(&self) -> &SampleClock {
+        &self.clock
+    }
+
+    fn time(&self) -> i64 {
+        self.time
+    }
 }
 
 // Coppied from https://github.com/rust-lang/rust/pull/88582
@@ -69,45 +200,162 @@ fn percentiles(values: impl Iterator<Item = i64>) -> Option<Vec<i64>> {
     ])
 }
 
-// `temps` must be sorted by time, and non-empty
-pub fn sumarize_temps(temps: &[Temps]) -> event::ThermalSummary {
+impl Temps {
+    pub fn zone(&self, zone: &str) -> Option<i64> {
+        self.zones.get(zone).copied()
+    }
+}
+
+/// Percentiles for each zone in any of `temps`
+pub fn zone_percentiles(temps: &[Temps]) -> BTreeMap<String, Vec<i64>> {
+    let mut zones = BTreeMap::new();
+    for zone in temps.iter().flat_map(|x| x.zones.keys()) {
+        if !zones.contains_key(zone) {
+            if let Some(values) = percentiles(temps.iter().filter_map(|x| x.zone(zone))) {
+                zones.insert(zone.clone(), values);
+            }
+        }
+    }
+    zones
+}
+
+// `temps` must be sorted by time, and non-empty. `None` if a zone required in
+// the summary wasn't read in any sample. The server's model has no fields yet
+// for other zones, like `gpu` and `loc`.
+pub fn sumarize_temps(temps: &[Temps]) -> Option<event::ThermalSummary> {
     assert!(!temps.is_empty());
 
+    let mut zones = zone_percentiles(temps);
+
     let start_time = temps.first().unwrap().time;
     let end_time = temps.last().unwrap().time;
-    // Assume system up one minute per sample
-    // TODO: better method
-    let system_up_time = temps.len() as i64 * TEMP_SAMPLE_SECONDS;
+    let system_up_time = up_times(temps).map(|(_, x)| x).sum();
 
-    event::ThermalSummary {
+    Some(event::ThermalSummary {
         bat_zone_ac_charging_ptile: percentiles(
             temps
                 .iter()
                 .filter(|x| x.on_ac && x.charging)
-                .map(|x| x.bat),
+                .filter_map(|x| x.zone("bat")),
         ),
         bat_zone_ac_not_charging_ptile: percentiles(
             temps
                 .iter()
                 .filter(|x| x.on_ac && !x.charging)
-                .map(|x| x.bat),
+                .filter_map(|x| x.zone("bat")),
+        ),
+        bat_zone_dc_ptile: percentiles(
+            temps
+                .iter()
+                .filter(|x| !x.on_ac)
+                .filter_map(|x| x.zone("bat")),
         ),
-        bat_zone_dc_ptile: percentiles(temps.iter().filter(|x| !x.on_ac).map(|x| x.bat)),
-        chg_zone_ptile: percentiles(temps.iter().map(|x| x.chg)).unwrap(),
-        cpu_zone_ptile: percentiles(temps.iter().map(|x| x.cpu)).unwrap(),
+        chg_zone_ptile: zones.remove("chg")?,
+        cpu_zone_ptile: zones.remove("cpu")?,
         end_time: format_unix_time(end_time),
-        ext_zone_ptile: percentiles(temps.iter().map(|x| x.ext)).unwrap(),
+        ext_zone_ptile: zones.remove("ext")?,
         num_samples: temps.len() as i64,
         start_time: format_unix_time(start_time),
         system_up_time,
+    })
+}
+
+// Fan cycles needed for a summary
+const MIN_FAN_CYCLES: usize = 20;
+
+/// Age of fan samples, in seconds, after which they are dropped without
+/// waiting for enough cycles to summarize them
+pub const MAX_FAN_SAMPLE_AGE: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug)]
+struct FanCycle {
+    length: i64,
+    time_to_max_speed: i64,
+    sum_speed: i64,
+}
+
+// Finished cycles, where the fan turns on then off again, with the index of
+// the sample ending each.
+fn fan_cycles(fans: &[Fan]) -> Vec<(usize, FanCycle)> {
+    let mut cycles = Vec::new();
+    let mut cycle_start = None;
+    let mut prev_on = None;
+    for (i, fan) in fans.iter().enumerate() {
+        // A gap in samples means the system was off or suspended
+        if i == 0 || !continuous(&fans[i - 1], fan) {
+            cycle_start = None;
+            prev_on = None;
+        }
+
+        let on = fan.rpm > 0;
+        match (prev_on, on) {
+            (Some(false), true) => {
+                cycle_start = Some(i);
+            }
+            (Some(true), false) => {
+                if let Some(start) = cycle_start.take() {
+                    let samples = &fans[start..i];
+                    // First sample at max speed
+                    let max = samples.iter().rev().max_by_key(|x| x.rpm).unwrap();
+                    cycles.push((
+                        i,
+                        FanCycle {
+                            length: fan.time - fans[start].time,
+                            time_to_max_speed: max.time - fans[start].time,
+                            sum_speed: samples.iter().map(|x| x.rpm).sum(),
+                        },
+                    ));
+                }
+            }
+            _ => {}
+        }
+        prev_on = Some(on);
     }
+    cycles
 }
 
+// `fans` must be sorted by time. If there are enough cycles, returns the
+// summary and the last sample it covers.
+pub fn sumarize_fan_cycles(fans: &[Fan]) -> Option<(event::CoolingFanCyclesSummary, &Fan)> {
+    let cycles = fan_cycles(fans);
+    if cycles.len() < MIN_FAN_CYCLES {
+        return None;
+    }
+
+    let end = cycles.last().unwrap().0;
+    let start_time = fans.first().unwrap().time;
+    let end_time = fans[end].time;
+    let system_up_time = up_times(&fans[..=end]).map(|(_, x)| x).sum();
+
+    Some((
+        event::CoolingFanCyclesSummary {
+            cycle_length_percentile: percentiles(cycles.iter().map(|(_, x)| x.length)).unwrap(),
+            end_time: format_unix_time(end_time),
+            num_cycles: cycles.len() as i64,
+            start_time: format_unix_time(start_time),
+            sum_speed_percentile: percentiles(cycles.iter().map(|(_, x)| x.sum_speed)).unwrap(),
+            system_up_time,
+            time_to_max_speed_percentile: percentiles(
+                cycles.iter().map(|(_, x)| x.time_to_max_speed),
+            )
+            .unwrap(),
+        },
+        &fans[end],
+    ))
+}
+
+// Power state of each sample is assumed to last until the next
 pub fn sumarize_battery_life(temps: &[Temps]) -> Option<event::BatteryLife> {
-    // Assume 1 minute samples
-    let total_ac_charging_time = temps.iter().filter(|x| x.on_ac && x.charging).count() as i64;
-    let total_ac_time = temps.iter().filter(|x| x.on_ac).count() as i64;
-    let total_dc_time = temps.iter().filter(|x| !x.on_ac).count() as i64;
+    let minutes = |f: fn(&Temps) -> bool| {
+        up_times(temps)
+            .filter(|(x, _)| f(x))
+            .map(|(_, x)| x)
+            .sum::<i64>()
+            / 60
+    };
+    let total_ac_charging_time = minutes(|x| x.on_ac && x.charging);
+    let total_ac_time = minutes(|x| x.on_ac);
+    let total_dc_time = minutes(|x| !x.on_ac);
 
     let path = crate::battery()?;
 
@@ -125,95 +373,299 @@ pub fn sumarize_battery_life(temps: &[Temps]) -> Option<event::BatteryLife> {
     })
 }
 
+/// Intervals between thermal samples, in seconds, from `/etc/hp-vendor.conf`
+/// or the server config
+#[derive(Debug, PartialEq, Eq)]
+pub struct SampleIntervals {
+    interval: i64,
+    battery_interval: Option<i64>,
+    stress_interval: Option<i64>,
+    stress_temp: i64,
+}
+
+impl SampleIntervals {
+    /// `server` is the config last received from the server
+    pub fn new(server: &SamplingConf) -> Self {
+        let conf = hp_vendor_conf().sampling.or(server);
+        // An interval of 0 would disarm the timer
+        let seconds = |x: u64| x.max(1) as i64;
+        Self {
+            interval: conf.interval.map_or(TEMP_SAMPLE_SECONDS, seconds),
+            battery_interval: conf.battery_interval.map(seconds),
+            stress_interval: conf.stress_interval.map(seconds),
+            stress_temp: conf.stress_temp.unwrap_or(DEFAULT_STRESS_TEMP),
+        }
+    }
+
+    /// Interval until the next sample, the shortest that applies given the
+    /// last one
+    pub fn next(&self, temps: Option<&Temps>) -> i64 {
+        let mut interval = self.interval;
+        if let Some(temps) = temps {
+            if let (Some(battery_interval), false) = (self.battery_interval, temps.on_ac) {
+                interval = interval.min(battery_interval);
+            }
+            let max_temp = temps.zones.values().copied().max();
+            if let (Some(stress_interval), Some(max_temp)) = (self.stress_interval, max_temp) {
+                if max_temp >= self.stress_temp {
+                    interval = interval.min(stress_interval);
+                }
+            }
+        }
+        interval
+    }
+}
+
+// A sysfs attribute a sensor is read from
 #[derive(Debug)]
-pub struct Sensors {
-    ac_device: udev::Device,
-    bat_device: udev::Device,
-    hwmon_acpi_device: udev::Device,
-    hwmon_hp_device: udev::Device,
+struct Reading {
+    device: udev::Device,
+    attribute: String,
 }
 
-impl Sensors {
-    pub fn new() -> Option<Self> {
+impl Reading {
+    fn new(source: &Source) -> Option<Self> {
+        let (subsystem, key, value, attribute) = match source {
+            Source::Hwmon { name, input } => ("hwmon", "name", name, format!("{}_input", input)),
+            Source::ThermalZone { type_ } => ("thermal", "type", type_, "temp".to_string()),
+        };
         let mut enumerator = udev::Enumerator::new().ok()?;
-        enumerator.match_subsystem("power_supply").ok()?;
-        enumerator.match_attribute("type", "Mains").ok()?;
-        let ac_device = enumerator.scan_devices().ok()?.next()?;
+        enumerator.match_subsystem(subsystem).ok()?;
+        enumerator.match_attribute(key, value).ok()?;
+        let device = enumerator.scan_devices().ok()?.next()?;
+        Some(Self { device, attribute })
+    }
 
-        let mut enumerator = udev::Enumerator::new().ok()?;
-        enumerator.match_subsystem("power_supply").ok()?;
-        enumerator.match_attribute("type", "Battery").ok()?;
-        let bat_device = enumerator.scan_devices().ok()?.next()?;
+    fn value(&self) -> Option<i64> {
+        self.device
+            .attribute_value(&self.attribute)?
+            .to_str()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
 
-        let mut enumerator = udev::Enumerator::new().ok()?;
-        enumerator.match_subsystem("hwmon").ok()?;
-        enumerator.match_attribute("name", "acpitz").ok()?;
-        let hwmon_acpi_device = enumerator.scan_devices().ok()?.next()?;
+fn power_supply(type_: &str) -> Option<udev::Device> {
+    let mut enumerator = udev::Enumerator::new().ok()?;
+    enumerator.match_subsystem("power_supply").ok()?;
+    enumerator.match_attribute("type", type_).ok()?;
+    enumerator.scan_devices().ok()?.next()
+}
 
-        let mut enumerator = udev::Enumerator::new().ok()?;
-        enumerator.match_subsystem("hwmon").ok()?;
-        enumerator.match_attribute("name", "hp_vendor").ok()?;
-        let hwmon_hp_device = enumerator.scan_devices().ok()?.next()?;
+// Doesn't seem to be a way to clear udev sysattr cache
+fn reopen(device: &mut udev::Device) {
+    if let Ok(new_device) = udev::Device::from_syspath(device.syspath()) {
+        *device = new_device;
+    }
+}
+
+/// Sensors from the `Profile` for the board. Zones that can't be found are
+/// left out of samples.
+#[derive(Debug)]
+pub struct Sensors {
+    ac_device: Option<udev::Device>,
+    bat_device: Option<udev::Device>,
+    readings: HashMap<String, Reading>,
+}
+
+impl Sensors {
+    /// `None` if no sensors in the profile are found
+    pub fn new() -> Option<Self> {
+        let mut readings = HashMap::new();
+        for (zone, source) in Profile::load().iter() {
+            match Reading::new(source) {
+                Some(reading) => {
+                    readings.insert(zone.to_string(), reading);
+                }
+                None => eprintln!("Sensor for `{}` not found: {:?}", zone, source),
+            }
+        }
+        if readings.is_empty() {
+            return None;
+        }
+        for zone in ZONES {
+            if !readings.contains_key(*zone) {
+                eprintln!("No sensor for zone `{}`", zone);
+            }
+        }
 
         Some(Self {
-            ac_device,
-            bat_device,
-            hwmon_acpi_device,
-            hwmon_hp_device,
+            ac_device: power_supply("Mains"),
+            bat_device: power_supply("Battery"),
+            readings,
         })
     }
 
-    // Doesn't seem to be a way to clear udev sysattr cache
     pub fn update(&mut self) {
-        fn reopen(device: &udev::Device) -> Option<udev::Device> {
-            udev::Device::from_syspath(device.syspath()).ok()
-        }
-        if let Some(device) = reopen(&self.ac_device) {
-            self.ac_device = device;
+        for device in self.ac_device.iter_mut().chain(&mut self.bat_device) {
+            reopen(device);
         }
-        if let Some(device) = reopen(&self.bat_device) {
-            self.bat_device = device;
-        }
-        if let Some(device) = reopen(&self.hwmon_acpi_device) {
-            self.hwmon_acpi_device = device;
-        }
-        if let Some(device) = reopen(&self.hwmon_hp_device) {
-            self.hwmon_hp_device = device;
+        for reading in self.readings.values_mut() {
+            reopen(&mut reading.device);
         }
     }
 
-    pub fn fan(&self) -> Option<i64> {
-        self.hwmon_hp_device
-            .attribute_value("fan1_input")?
-            .to_str()?
-            .trim()
-            .parse()
-            .ok()
+    pub fn fan(&self) -> Option<Fan> {
+        let rpm = self.readings.get("fan")?.value()?;
+        Some(Fan {
+            rpm,
+            time: unix_time(),
+            clock: SampleClock::now(),
+        })
+    }
+
+    // In degrees Celsius
+    fn temp(&self, zone: &str) -> Option<i64> {
+        Some(self.readings.get(zone)?.value()? / 1000)
     }
 
     pub fn thermal(&self) -> Option<Temps> {
-        let temp_n = |n| {
-            let value = self
-                .hwmon_acpi_device
-                .attribute_value(format!("temp{}_input", n))?;
-            Some(value.to_str()?.trim().parse::<i64>().ok()? / 1000)
+        // Without an adapter or battery, assume a desktop
+        let on_ac = match &self.ac_device {
+            Some(device) => device.attribute_value("online")?.to_str()?.trim() == "1",
+            None => true,
+        };
+        let charging = match &self.bat_device {
+            Some(device) => device.attribute_value("status")?.to_str()?.trim() == "Charging",
+            None => false,
         };
 
-        let on_ac = self.ac_device.attribute_value("online")?.to_str()?.trim() == "1";
-        let charging = self.bat_device.attribute_value("status")?.to_str()?.trim() == "Charging";
+        let zones = ZONES
+            .iter()
+            .filter_map(|zone| Some((zone.to_string(), self.temp(zone)?)))
+            .collect::<BTreeMap<_, _>>();
+        if zones.is_empty() {
+            return None;
+        }
 
         Some(Temps {
-            cpu: temp_n(1)?,
-            // Unused
-            // gpu: temp_n(2)?,
-            ext: temp_n(3)?,
-            // Unused
-            // loc: temp_n(4)?,
-            bat: temp_n(5)?,
-            chg: temp_n(6)?,
+            zones,
             on_ac,
             charging,
             time: unix_time(),
+            clock: SampleClock::now(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fan(time: i64, boot_id: Option<&str>, monotonic: Option<i64>, boottime: Option<i64>) -> Fan {
+        Fan {
+            rpm: 0,
+            time,
+            clock: SampleClock {
+                boot_id: boot_id.map(str::to_string),
+                monotonic,
+                boottime,
+            },
+        }
+    }
+
+    #[test]
+    fn sample_gaps() {
+        // Previous and next sample, with the expected up time and if continuous
+        let cases = [
+            // Awake, using the monotonic clock
+            (
+                fan(0, Some("a"), Some(0), Some(0)),
+                fan(90, Some("a"), Some(60_000), Some(60_000)),
+                60,
+                true,
+            ),
+            // Within the allowance for clock skew
+            (
+                fan(0, Some("a"), Some(0), Some(0)),
+                fan(60, Some("a"), Some(60_000), Some(60_500)),
+                60,
+                true,
+            ),
+            // Suspended for an hour
+            (
+                fan(0, Some("a"), Some(0), Some(0)),
+                fan(3660, Some("a"), Some(60_000), Some(3_660_000)),
+                60,
+                false,
+            ),
+            // Rebooted
+            (
+                fan(0, Some("a"), Some(0), Some(0)),
+                fan(60, Some("b"), Some(1000), Some(1000)),
+                0,
+                false,
+            ),
+            // Stored without clocks, so the gap decides
+            (
+                fan(0, None, None, None),
+                fan(60, None, None, None),
+                60,
+                true,
+            ),
+            (
+                fan(0, None, None, None),
+                fan(MAX_SAMPLE_GAP, None, None, None),
+                MAX_SAMPLE_GAP,
+                true,
+            ),
+            (
+                fan(0, None, None, None),
+                fan(600, None, None, None),
+                0,
+                false,
+            ),
+            // Only one stored with clocks
+            (
+                fan(0, None, None, None),
+                fan(60, Some("a"), Some(60_000), Some(60_000)),
+                60,
+                true,
+            ),
+        ];
+        for (prev, next, up, cont) in &cases {
+            assert_eq!(up_time(prev, next), *up, "{:?} {:?}", prev, next);
+            assert_eq!(continuous(prev, next), *cont, "{:?} {:?}", prev, next);
+        }
+    }
+
+    #[test]
+    fn fan_cycle_detection() {
+        // Samples as time and rpm, and the expected end index, length, time to
+        // max speed, and sum of speeds of each cycle
+        let cases: &[(&[(i64, i64)], &[(usize, i64, i64, i64)])] = &[
+            // Already on at the first sample
+            (&[(0, 1000), (60, 0)], &[]),
+            (
+                &[(0, 0), (60, 1000), (120, 3000), (180, 2000), (240, 0)],
+                &[(4, 180, 60, 6000)],
+            ),
+            // First sample at max speed
+            (
+                &[(0, 0), (60, 2000), (120, 2000), (180, 0)],
+                &[(3, 120, 0, 4000)],
+            ),
+            (
+                &[(0, 0), (60, 1000), (120, 0), (180, 2000), (240, 0)],
+                &[(2, 60, 0, 1000), (4, 60, 0, 2000)],
+            ),
+            // Suspended or off in the middle of a cycle
+            (&[(0, 0), (60, 1000), (600, 1000), (660, 0)], &[]),
+        ];
+        for (samples, expected) in cases {
+            let fans = samples
+                .iter()
+                .map(|(time, rpm)| Fan {
+                    rpm: *rpm,
+                    ..fan(*time, None, None, None)
+                })
+                .collect::<Vec<_>>();
+            let cycles = fan_cycles(&fans)
+                .into_iter()
+                .map(|(i, x)| (i, x.length, x.time_to_max_speed, x.sum_speed))
+                .collect::<Vec<_>>();
+            assert_eq!(&cycles, expected, "{:?}", samples);
+        }
+    }
+}
@@ -9,10 +9,10 @@ use std::process::Command;
 const SERVICE: &str = "hp-vendor.service";
 const TIMERS: &[&str] = &["hp-vendor-daily.timer", "hp-vendor-upload.timer"];
 
-/// Restarts daemon if running, to handle frequencies change
-pub fn try_restart_daemon() {
+/// Reloads daemon if running, to handle frequencies change
+pub fn try_reload_daemon() {
     let _ = Command::new("systemctl")
-        .args(&["try-restart", SERVICE])
+        .args(&["try-reload-or-restart", SERVICE])
         .status();
 }
 
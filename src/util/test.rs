@@ -0,0 +1,37 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{fs, path::Path};
+
+/// Unique directory of fake sysfs or log files, removed when dropped
+pub struct FakeDir(tempfile::TempDir);
+
+impl Default for FakeDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeDir {
+    pub fn new() -> Self {
+        Self(
+            tempfile::Builder::new()
+                .prefix("hp-vendor-")
+                .tempdir()
+                .unwrap(),
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    /// Writes `contents` to `path` relative to the directory, creating any
+    /// parent directories
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
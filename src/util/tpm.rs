@@ -0,0 +1,304 @@
+// SPDX-FileCopyrightText: 2022 Hewlett-Packard Development Company, L.P.
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::event::{read_file, State, TrustedPlatformModule};
+
+const TPM2_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM2_CC_GET_CAPABILITY: u32 = 0x0000017A;
+const TPM2_CAP_TPM_PROPERTIES: u32 = 0x00000006;
+
+const TPM2_PT_FIXED: u32 = 0x100;
+const TPM2_PT_LEVEL: u32 = TPM2_PT_FIXED + 1;
+const TPM2_PT_REVISION: u32 = TPM2_PT_FIXED + 2;
+const TPM2_PT_MANUFACTURER: u32 = TPM2_PT_FIXED + 5;
+const TPM2_PT_VENDOR_STRING_1: u32 = TPM2_PT_FIXED + 6;
+const TPM2_PT_VENDOR_STRING_4: u32 = TPM2_PT_FIXED + 9;
+const TPM2_PT_FIRMWARE_VERSION_1: u32 = TPM2_PT_FIXED + 11;
+const TPM2_PT_FIRMWARE_VERSION_2: u32 = TPM2_PT_FIXED + 12;
+
+const TPM2_PT_VAR: u32 = 0x200;
+const TPM2_PT_PERMANENT: u32 = TPM2_PT_VAR;
+const TPM2_PT_STARTUP_CLEAR: u32 = TPM2_PT_VAR + 1;
+
+const NOT_SUPPORTED: &str = "Not Supported";
+
+fn not_supported() -> String {
+    NOT_SUPPORTED.to_string()
+}
+
+// Four ASCII characters, padded with nulls or spaces
+fn ascii_u32(value: u32) -> Option<String> {
+    let bytes = value.to_be_bytes();
+    if !bytes
+        .iter()
+        .all(|x| *x == 0 || x.is_ascii_graphic() || *x == b' ')
+    {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string(),
+    )
+}
+
+fn manufacturer_id(value: u32) -> String {
+    ascii_u32(value).unwrap_or_else(|| format!("{:#X}", value))
+}
+
+// `caps` file of TPM 1.2 devices
+#[derive(Debug, Default)]
+struct Caps {
+    manufacturer: Option<u32>,
+    tcg_version: Option<String>,
+    firmware_version: Option<String>,
+}
+
+impl Caps {
+    fn parse(caps: &str) -> Self {
+        let mut res = Self::default();
+        for line in caps.lines() {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value.trim();
+                match key.trim() {
+                    "Manufacturer" => {
+                        let value = value.trim_start_matches("0x");
+                        res.manufacturer = u32::from_str_radix(value, 16).ok();
+                    }
+                    "TCG version" => res.tcg_version = Some(value.to_string()),
+                    "Firmware version" => res.firmware_version = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        res
+    }
+}
+
+fn get_capability(file: &mut fs::File, property: u32, count: u32) -> Option<HashMap<u32, u32>> {
+    let mut cmd = Vec::with_capacity(22);
+    cmd.extend_from_slice(&TPM2_ST_NO_SESSIONS.to_be_bytes());
+    cmd.extend_from_slice(&22u32.to_be_bytes());
+    cmd.extend_from_slice(&TPM2_CC_GET_CAPABILITY.to_be_bytes());
+    cmd.extend_from_slice(&TPM2_CAP_TPM_PROPERTIES.to_be_bytes());
+    cmd.extend_from_slice(&property.to_be_bytes());
+    cmd.extend_from_slice(&count.to_be_bytes());
+    file.write_all(&cmd).ok()?;
+
+    let mut resp = [0; 4096];
+    let len = file.read(&mut resp).ok()?;
+    let resp = &resp[..len];
+    let u32_at = |offset: usize| {
+        Some(u32::from_be_bytes(
+            resp.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+
+    // Header is tag, size, and response code; then `moreData`, `capability`,
+    // and a list of `TPMS_TAGGED_PROPERTY`
+    if u32_at(6)? != 0 {
+        return None;
+    }
+    let count = u32_at(15)? as usize;
+    (0..count)
+        .map(|i| Some((u32_at(19 + i * 8)?, u32_at(23 + i * 8)?)))
+        .collect()
+}
+
+// Queries properties of a TPM 2.0 device, through the kernel's resource manager
+fn tpm2_properties<P: AsRef<Path>>(path: P) -> Option<HashMap<u32, u32>> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .ok()?;
+    let mut properties = get_capability(&mut file, TPM2_PT_FIXED, 64)?;
+    if let Some(var) = get_capability(&mut file, TPM2_PT_VAR, 2) {
+        properties.extend(var);
+    }
+    Some(properties)
+}
+
+fn tpm2(
+    dev_path: &Path,
+    number: &str,
+    description: Option<String>,
+    physical_presence_version: Option<String>,
+) -> TrustedPlatformModule {
+    let properties = tpm2_properties(dev_path.join(format!("tpmrm{}", number)));
+    let property = |x| properties.as_ref()?.get(&x).copied();
+
+    let manufacturer_version = match (
+        property(TPM2_PT_FIRMWARE_VERSION_1),
+        property(TPM2_PT_FIRMWARE_VERSION_2),
+    ) {
+        (Some(v1), Some(v2)) => {
+            format!("{}.{}.{}.{}", v1 >> 16, v1 & 0xffff, v2 >> 16, v2 & 0xffff)
+        }
+        _ => not_supported(),
+    };
+
+    let vendor_string = (TPM2_PT_VENDOR_STRING_1..=TPM2_PT_VENDOR_STRING_4)
+        .filter_map(|x| ascii_u32(property(x)?))
+        .collect::<String>();
+    let manufacturer_version_info = if !vendor_string.is_empty() {
+        vendor_string
+    } else {
+        description.unwrap_or_else(not_supported)
+    };
+
+    // Like "2.0, 0, 1.38" on Windows
+    let trusted_computing_group_version =
+        match (property(TPM2_PT_LEVEL), property(TPM2_PT_REVISION)) {
+            (Some(level), Some(revision)) => {
+                format!("2.0, {}, {}.{:02}", level, revision / 100, revision % 100)
+            }
+            _ => "2.0".to_string(),
+        };
+
+    // TPM 2.0 has no separate activation; use state of the storage and
+    // endorsement hierarchies. Assume enabled, if the kernel found it.
+    let enabled = property(TPM2_PT_STARTUP_CLEAR).map_or(true, |x| x & 0b110 == 0b110);
+
+    TrustedPlatformModule {
+        manufacturer_id: property(TPM2_PT_MANUFACTURER)
+            .map(manufacturer_id)
+            .unwrap_or_else(not_supported),
+        manufacturer_version,
+        manufacturer_version_info,
+        physical_presence_version,
+        state: State::Added,
+        tpm_activated: Some(enabled),
+        tpm_enabled: Some(enabled),
+        // `ownerAuthSet`
+        tpm_has_owner: property(TPM2_PT_PERMANENT).map(|x| x & 0b1 != 0),
+        trusted_computing_group_version,
+    }
+}
+
+fn tpm12(
+    attr: impl Fn(&str) -> Option<String>,
+    description: Option<String>,
+    physical_presence_version: Option<String>,
+) -> TrustedPlatformModule {
+    let caps = attr("caps").map_or_else(Caps::default, |x| Caps::parse(&x));
+    let flag = |name| attr(name).map(|x| x == "1");
+
+    TrustedPlatformModule {
+        manufacturer_id: caps
+            .manufacturer
+            .map(manufacturer_id)
+            .unwrap_or_else(not_supported),
+        manufacturer_version: caps.firmware_version.unwrap_or_else(not_supported),
+        manufacturer_version_info: description.unwrap_or_else(not_supported),
+        physical_presence_version,
+        state: State::Added,
+        tpm_activated: flag("active"),
+        tpm_enabled: flag("enabled"),
+        tpm_has_owner: flag("owned"),
+        trusted_computing_group_version: caps.tcg_version.unwrap_or_else(|| "1.2".to_string()),
+    }
+}
+
+/// TPMs in `class_path` (normally `/sys/class/tpm`), with device nodes in
+/// `dev_path` (normally `/dev`)
+pub fn tpms<P: AsRef<Path>, Q: AsRef<Path>>(
+    class_path: P,
+    dev_path: Q,
+) -> Vec<TrustedPlatformModule> {
+    let mut tpms = Vec::new();
+    for entry in fs::read_dir(class_path).into_iter().flatten().flatten() {
+        let file_name = entry.file_name();
+        let number = match file_name.to_str().and_then(|x| x.strip_prefix("tpm")) {
+            Some(number) => number.to_string(),
+            None => {
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        // Attributes moved from the parent device in newer kernels
+        let attr = |name: &str| -> Option<String> {
+            read_file(path.join(name)).or_else(|| read_file(path.join("device").join(name)))
+        };
+        let description = attr("description");
+        let physical_presence_version = attr("ppi/version");
+
+        // `tpm_version_major` is missing before Linux 5.6
+        let version_major = read_file(path.join("tpm_version_major")).unwrap_or_else(|| {
+            if attr("caps").is_some() {
+                1
+            } else {
+                2
+            }
+        });
+
+        tpms.push(if version_major == 1 {
+            tpm12(attr, description, physical_presence_version)
+        } else {
+            tpm2(
+                dev_path.as_ref(),
+                &number,
+                description,
+                physical_presence_version,
+            )
+        });
+    }
+    tpms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test::FakeDir;
+
+    #[test]
+    fn fake_sysfs() {
+        let dir = FakeDir::new();
+
+        dir.write("class/tpm0/tpm_version_major", "1\n");
+        dir.write(
+            "class/tpm0/device/caps",
+            "Manufacturer: 0x49465800\nTCG version: 1.2\nFirmware version: 6.40\n",
+        );
+        dir.write("class/tpm0/device/enabled", "1\n");
+        dir.write("class/tpm0/device/active", "1\n");
+        dir.write("class/tpm0/device/owned", "0\n");
+        dir.write("class/tpm0/ppi/version", "1.3\n");
+
+        dir.write("class/tpm1/tpm_version_major", "2\n");
+        dir.write("class/tpm1/device/description", "TPM 2.0 Device\n");
+
+        let mut tpms = tpms(dir.path().join("class"), dir.path().join("dev"));
+        tpms.sort_by(|a, b| {
+            a.trusted_computing_group_version
+                .cmp(&b.trusted_computing_group_version)
+        });
+
+        assert_eq!(tpms.len(), 2);
+
+        assert_eq!(tpms[0].manufacturer_id, "IFX");
+        assert_eq!(tpms[0].manufacturer_version, "6.40");
+        assert_eq!(tpms[0].trusted_computing_group_version, "1.2");
+        assert_eq!(tpms[0].physical_presence_version.as_deref(), Some("1.3"));
+        assert_eq!(tpms[0].tpm_enabled, Some(true));
+        assert_eq!(tpms[0].tpm_activated, Some(true));
+        assert_eq!(tpms[0].tpm_has_owner, Some(false));
+
+        // No device node to query
+        assert_eq!(tpms[1].manufacturer_id, NOT_SUPPORTED);
+        assert_eq!(tpms[1].manufacturer_version, NOT_SUPPORTED);
+        assert_eq!(tpms[1].manufacturer_version_info, "TPM 2.0 Device");
+        assert_eq!(tpms[1].trusted_computing_group_version, "2.0");
+        assert_eq!(tpms[1].tpm_has_owner, None);
+    }
+}